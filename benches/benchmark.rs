@@ -252,6 +252,60 @@ pub fn get_quad_cuckoo(c: &mut Criterion) {
         );
     }
 }
+// Absent keys are mostly rejected by their fingerprints without a key comparison
+pub fn get_miss_quad_cuckoo(c: &mut Criterion) {
+    let mut group = c.benchmark_group("QuadCuckooHashMap get miss");
+    group.bench_function("load_factor=0.990", |b| {
+        let mut table = QuadCuckooHashMap::with_load_factor(0.99);
+        for i in 0..100_000 {
+            assert_eq!(table.insert(i, i), None);
+        }
+        b.iter(|| {
+            let n = black_box(100_000);
+            for i in n..2 * n {
+                assert_eq!(table.get(&i), None);
+            }
+        })
+    });
+}
+
+pub fn get_miss_chaining(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ChainingHashMap get miss");
+    for bloom_filter in [false, true] {
+        group.bench_function(format!("bloom_filter={bloom_filter}"), |b| {
+            let mut table = ChainingHashMap::with_load_factor(4.0);
+            table.set_bloom_filter(bloom_filter);
+            for i in 0..100_000 {
+                assert_eq!(table.insert(i, i), None);
+            }
+            b.iter(|| {
+                let n = black_box(100_000);
+                for i in n..2 * n {
+                    assert_eq!(table.get(&i), None);
+                }
+            })
+        });
+    }
+}
+
+pub fn get_repeated_chaining(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ChainingHashMap get repeated key");
+    for lookup_memo in [false, true] {
+        group.bench_function(format!("lookup_memo={lookup_memo}"), |b| {
+            let mut table = ChainingHashMap::new();
+            table.set_lookup_memo(lookup_memo);
+            for i in 0..100_000 {
+                assert_eq!(table.insert(i, i), None);
+            }
+            b.iter(|| {
+                let key = black_box(12_345);
+                for _ in 0..100_000 {
+                    assert_eq!(table.get(&key), Some(&key));
+                }
+            })
+        });
+    }
+}
 
 pub fn compare_get(c: &mut Criterion) {
     let mut group = c.benchmark_group("HashMap compared get");
@@ -546,6 +600,51 @@ pub fn concurrent_get(c: &mut Criterion) {
     });
 }
 
+pub fn get_or_clone_concurrent(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ConcurrentChainingHashMap get_or_clone");
+    let table = ConcurrentChainingHashMap::new();
+    for i in 0..100_000_u64 {
+        assert_eq!(table.insert(i, Arc::new(i)), None);
+    }
+    group.bench_function("get", |b| {
+        b.iter(|| {
+            let n = black_box(100_000);
+            for i in 0..n {
+                assert_eq!(*table.get(&i).unwrap(), i);
+            }
+        })
+    });
+    group.bench_function("get_or_clone", |b| {
+        b.iter(|| {
+            let n = black_box(100_000);
+            for i in 0..n {
+                assert_eq!(table.get_or_clone(&i), Some(i));
+            }
+        })
+    });
+}
+
+#[cfg(feature = "rayon")]
+pub fn par_rehash_open(c: &mut Criterion) {
+    let mut group = c.benchmark_group("OpenHashMap rehash");
+    group.sample_size(10);
+    group.bench_function("par_rehash 1M", |b| {
+        b.iter_batched(
+            || {
+                let mut table = OpenHashMap::new();
+                for i in 0..1_000_000 {
+                    table.insert(i, i);
+                }
+                table
+            },
+            |mut table| table.par_rehash(),
+            criterion::BatchSize::LargeInput,
+        )
+    });
+}
+#[cfg(not(feature = "rayon"))]
+pub fn par_rehash_open(_c: &mut Criterion) {}
+
 criterion_group!(
     hash_table,
     insert_chaining,
@@ -556,9 +655,14 @@ criterion_group!(
     get_cuckoo,
     get_open,
     get_quad_cuckoo,
+    get_miss_quad_cuckoo,
+    get_miss_chaining,
+    get_repeated_chaining,
     compare_insert,
     compare_get,
     concurrent_insert,
     concurrent_get,
+    get_or_clone_concurrent,
+    par_rehash_open,
 );
 criterion_main!(hash_table);
@@ -1,17 +1,144 @@
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
-use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::sync::{Mutex, PoisonError};
+use std::{iter, mem};
+
+use rand::Rng;
+
+#[cfg(feature = "rayon")]
+use rayon::iter::{plumbing::UnindexedConsumer, IntoParallelIterator, ParallelIterator};
 
 pub struct HashMap<K: Hash + Eq, V> {
-    buckets: Vec<Option<Entry<K, V>>>,
+    buckets: Vec<Option<Node<K, V>>>,
     length: usize,
     load_factor: f64,
+    // Outcomes of insert since the last clear, to spot keys with a broken Hash or Eq
+    overwrite_count: usize,
+    new_key_count: usize,
+    seed: u64,
+    on_rehash: Option<RehashHook>,
+    bloom_filter: Option<BloomFilter>,
+    lookup_memo: Option<LookupMemo>,
+}
+
+// Called with the old and new bucket count whenever the buckets are rebuilt, including the
+// same-size rebuild of compact. The Mutex keeps the map Sync without asking Sync of the hook,
+// it is only reached through &mut self and never locked.
+type RehashHook = Mutex<Box<dyn FnMut(usize, usize) + Send>>;
+
+// A clear bit means no key with that hash was inserted since the filter was last rebuilt.
+// Removals leave their bits set, so stale bits only cost a chain walk until the next resize.
+struct BloomFilter {
+    words: Vec<u64>,
+}
+
+// Where the last key found by get or insert sat. It is never invalidated: a hit requires the node
+// there to hold an equal key, so a stale position only costs one key comparison.
+struct LookupMemo {
+    bucket: AtomicUsize,
+    position: AtomicUsize,
 }
 
-struct Entry<K: Hash + Eq, V> {
+struct Node<K: Hash + Eq, V> {
     key: K,
     value: V,
-    next: Option<Box<Entry<K, V>>>,
+    next: Option<Box<Node<K, V>>>,
+}
+
+pub enum Entry<'a, K: Hash + Eq, V> {
+    Occupied(OccupiedEntry<'a, K, V>),
+    Vacant(VacantEntry<'a, K, V>),
+}
+
+pub struct OccupiedEntry<'a, K: Hash + Eq, V> {
+    slot: Slot<'a, K, V>,
+    length: &'a mut usize,
+}
+
+pub struct VacantEntry<'a, K: Hash + Eq, V> {
+    key: K,
+    bucket: &'a mut Option<Node<K, V>>,
+    length: &'a mut usize,
+}
+
+#[cfg(feature = "rayon")]
+pub struct IntoParIter<K: Hash + Eq, V> {
+    buckets: Vec<Option<Node<K, V>>>,
+}
+
+// Location of an existing node: the head of a bucket or a link inside its chain
+enum Slot<'a, K: Hash + Eq, V> {
+    Head(&'a mut Option<Node<K, V>>),
+    Link(&'a mut Option<Box<Node<K, V>>>),
+}
+
+#[derive(Debug)]
+pub struct DuplicateKeyError<K> {
+    pub key: K,
+}
+
+#[derive(Debug)]
+pub struct OccupiedError<'a, K, V> {
+    pub key: K,
+    pub value: V,
+    pub current: &'a mut V,
+}
+
+// Verdict of the retain_until callback; Stop leaves the current and all unvisited entries in place
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetainAction {
+    Keep,
+    Remove,
+    Stop,
+}
+
+// Filter bits per entry the table holds before growing, with two bits set per key
+const BLOOM_BITS_PER_ENTRY: usize = 8;
+
+fn scaled_capacity(capacity: usize, factor: usize) -> usize {
+    capacity.checked_mul(factor).expect("capacity overflow")
+}
+
+// Buckets that hold entries below the load factor, never fewer than a fresh table starts with
+fn required_buckets(entries: usize, load_factor: f64) -> usize {
+    let bucket_count = (entries as f64 / load_factor).floor();
+    assert!(bucket_count < usize::MAX as f64, "capacity overflow");
+    (bucket_count as usize + 1).max(64)
+}
+
+impl LookupMemo {
+    fn store(&self, bucket: usize, position: usize) {
+        self.bucket.store(bucket, Relaxed);
+        self.position.store(position, Relaxed);
+    }
+}
+
+impl BloomFilter {
+    fn new(entries: usize) -> Self {
+        let bit_count = scaled_capacity(entries, BLOOM_BITS_PER_ENTRY);
+        Self {
+            words: vec![0; bit_count.div_ceil(64).max(1)],
+        }
+    }
+    // The bucket index uses the low bits of the hash, so the positions are taken from the rest
+    fn positions(&self, hash: u64) -> [usize; 2] {
+        let bit_count = self.words.len() * 64;
+        [
+            (hash >> 32) as usize % bit_count,
+            (hash.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32) as usize % bit_count,
+        ]
+    }
+    fn insert(&mut self, hash: u64) {
+        for position in self.positions(hash) {
+            self.words[position / 64] |= 1 << (position % 64);
+        }
+    }
+    fn may_contain(&self, hash: u64) -> bool {
+        self.positions(hash)
+            .iter()
+            .all(|&position| self.words[position / 64] & (1 << (position % 64)) != 0)
+    }
 }
 
 impl<K: Hash + Eq, V> HashMap<K, V> {
@@ -19,7 +146,26 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
         Self::with_exact_capacity(0, 0.4)
     }
     pub fn with_capacity(capacity: usize) -> Self {
-        Self::with_exact_capacity(capacity * 8, 0.4)
+        Self::with_exact_capacity(scaled_capacity(capacity, 8), 0.4)
+    }
+    // Fails on the first key that is already present, handing that key back
+    pub fn from_pairs_checked<I: IntoIterator<Item = (K, V)>>(
+        iter: I,
+    ) -> Result<Self, DuplicateKeyError<K>> {
+        let mut table = Self::new();
+        for (key, value) in iter {
+            if let Err(error) = table.try_insert(key, value) {
+                return Err(DuplicateKeyError { key: error.key });
+            }
+        }
+        Ok(table)
+    }
+    // For reproducible bucket layouts, new() picks a random seed per table
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            seed,
+            ..Self::new()
+        }
     }
     pub fn with_load_factor(load_factor: f64) -> Self {
         Self::with_exact_capacity(0, load_factor)
@@ -29,6 +175,12 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
             buckets: (0..capacity).map(|_| None).collect(),
             length: 0,
             load_factor,
+            overwrite_count: 0,
+            new_key_count: 0,
+            seed: rand::thread_rng().gen(),
+            on_rehash: None,
+            bloom_filter: None,
+            lookup_memo: None,
         }
     }
     pub fn len(&self) -> usize {
@@ -37,6 +189,13 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
     pub fn is_empty(&self) -> bool {
         self.length == 0
     }
+    // Entries that fit before the next rehash, 0 until the first insert allocates buckets
+    pub fn capacity(&self) -> usize {
+        (self.buckets.len() as f64 * self.load_factor) as usize
+    }
+    pub fn load_factor(&self) -> f64 {
+        self.load_factor
+    }
     pub fn fill_factor(&self) -> f64 {
         if self.buckets.is_empty() {
             0.0
@@ -44,34 +203,171 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
             self.length as f64 / self.buckets.len() as f64
         }
     }
+    pub fn occupied_bucket_count(&self) -> usize {
+        self.buckets
+            .iter()
+            .filter(|bucket| bucket.is_some())
+            .count()
+    }
+    pub fn empty_bucket_count(&self) -> usize {
+        self.buckets.len() - self.occupied_bucket_count()
+    }
+    pub fn overwrite_count(&self) -> usize {
+        self.overwrite_count
+    }
+    pub fn new_key_count(&self) -> usize {
+        self.new_key_count
+    }
     pub fn clear(&mut self) {
         self.length = 0;
+        self.overwrite_count = 0;
+        self.new_key_count = 0;
         for element in self.buckets.iter_mut() {
             *element = None;
         }
+        self.reset_bloom_filter();
+    }
+
+    // Lets get and contains_key skip the chain walk for most absent keys, enabling it
+    // fills the filter from the keys already stored
+    pub fn set_bloom_filter(&mut self, enabled: bool) {
+        self.bloom_filter = enabled.then(|| BloomFilter::new(0));
+        self.reset_bloom_filter();
+    }
+
+    // Off by default. Repeated gets and inserts of the same key then skip hashing it.
+    pub fn set_lookup_memo(&mut self, enabled: bool) {
+        self.lookup_memo = enabled.then(|| LookupMemo {
+            bucket: AtomicUsize::new(0),
+            position: AtomicUsize::new(0),
+        });
+    }
+
+    fn memoized_node(&self, key: &K) -> Option<&Node<K, V>> {
+        let memo = self.lookup_memo.as_ref()?;
+        let bucket = self.buckets.get(memo.bucket.load(Relaxed))?;
+        let node = iter::successors(bucket.as_ref(), |node| node.next.as_deref())
+            .nth(memo.position.load(Relaxed))?;
+        (node.key == *key).then_some(node)
+    }
+
+    fn memoized_node_mut(&mut self, key: &K) -> Option<&mut Node<K, V>> {
+        let memo = self.lookup_memo.as_ref()?;
+        let position = memo.position.load(Relaxed);
+        let mut node = self.buckets.get_mut(memo.bucket.load(Relaxed))?.as_mut()?;
+        for _ in 0..position {
+            node = node.next.as_deref_mut()?;
+        }
+        (node.key == *key).then_some(node)
+    }
+
+    fn reset_bloom_filter(&mut self) {
+        let entries = self.capacity().max(self.buckets.len());
+        if let Some(filter) = &mut self.bloom_filter {
+            *filter = BloomFilter::new(entries);
+            for bucket in &self.buckets {
+                let mut current = bucket.as_ref();
+                while let Some(node) = current {
+                    filter.insert(Self::calculate_hash(&node.key, self.seed));
+                    current = node.next.as_deref();
+                }
+            }
+        }
     }
 
-    fn calculate_hash(key: &K) -> u64 {
+    fn calculate_hash(key: &K, seed: u64) -> u64 {
         let mut s = DefaultHasher::new();
+        s.write_u64(seed);
         key.hash(&mut s);
         s.finish()
     }
 
     pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.replace(key, value)
+    }
+
+    pub fn replace(&mut self, key: K, value: V) -> Option<V> {
+        self.insert_tracked(key, value).0
+    }
+
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<&mut V, OccupiedError<'_, K, V>> {
+        match self.locate(key) {
+            Ok(entry) => Ok(entry.insert(value)),
+            Err((entry, key)) => Err(OccupiedError {
+                current: entry.into_mut(),
+                key,
+                value,
+            }),
+        }
+    }
+
+    // Keeps an existing value, so the returned reference is always to the first one stored
+    pub fn insert_or_get(&mut self, key: K, value: V) -> &V {
+        self.entry(key).or_insert(value)
+    }
+
+    pub fn insert_tracked(&mut self, key: K, value: V) -> (Option<V>, bool) {
+        let rehashed = self.grow_if_needed();
+        if let Some(node) = self.memoized_node_mut(&key) {
+            node.key = key;
+            let old = mem::replace(&mut node.value, value);
+            self.overwrite_count += 1;
+            return (Some(old), rehashed);
+        }
+        let hash = Self::calculate_hash(&key, self.seed);
+        let index = hash as usize % self.buckets.len();
+        let bucket = &mut self.buckets[index];
+        // Overwrite an equal key in place, otherwise push to the front of the chain
+        if let Some(mut node) = bucket.as_mut() {
+            let mut position = 0;
+            loop {
+                if node.key == key {
+                    if let Some(memo) = &self.lookup_memo {
+                        memo.store(index, position);
+                    }
+                    node.key = key;
+                    self.overwrite_count += 1;
+                    return (Some(mem::replace(&mut node.value, value)), rehashed);
+                }
+                match &mut node.next {
+                    Some(next) => node = next,
+                    None => break,
+                }
+                position += 1;
+            }
+        }
+        let next = bucket.take().map(Box::new);
+        *bucket = Some(Node { key, value, next });
+        if let Some(filter) = &mut self.bloom_filter {
+            filter.insert(hash);
+        }
+        if let Some(memo) = &self.lookup_memo {
+            memo.store(index, 0);
+        }
+        self.length += 1;
+        self.new_key_count += 1;
+        (None, rehashed)
+    }
+
+    fn grow_if_needed(&mut self) -> bool {
         if self.buckets.is_empty() {
             self.buckets = (0..64).map(|_| None).collect();
+            self.reset_bloom_filter();
         }
         if self.fill_factor() >= self.load_factor {
             self.rehash();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn push_front(&mut self, entry: Node<K, V>) {
+        let hash = Self::calculate_hash(&entry.key, self.seed);
+        if let Some(filter) = &mut self.bloom_filter {
+            filter.insert(hash);
         }
-        let old = self.remove(&key);
-        let hash = Self::calculate_hash(&key);
         let index = hash as usize % self.buckets.len();
-        let entry = Entry {
-            key,
-            value,
-            next: None,
-        };
         match self.buckets.get_mut(index) {
             Some(option) => match option {
                 Some(bucket) => {
@@ -86,30 +382,91 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
                 unreachable!("index out of bounds");
             }
         }
-        self.length += 1;
-        old
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        let required = self
+            .length
+            .checked_add(additional)
+            .expect("capacity overflow");
+        let bucket_count = required_buckets(required, self.load_factor);
+        if bucket_count > self.buckets.len() {
+            self.resize(bucket_count);
+        }
+    }
+
+    // Returns how many of the pairs overwrote a key that was already present
+    pub fn insert_all<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) -> usize {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        let mut overwrites = 0;
+        for (key, value) in iter {
+            if self.insert(key, value).is_some() {
+                overwrites += 1;
+            }
+        }
+        overwrites
+    }
+
+    // Only ever shrinks, keeping room for at least `min_capacity` entries
+    pub fn shrink_to(&mut self, min_capacity: usize) {
+        let required = self.length.max(min_capacity);
+        let bucket_count = required_buckets(required, self.load_factor);
+        if bucket_count < self.buckets.len() {
+            self.resize(bucket_count);
+        }
+    }
+
+    // Rebuilds every chain from freshly boxed nodes at the same bucket count, so nodes scattered
+    // by churn end up allocated next to the rest of their chain
+    pub fn compact(&mut self) {
+        self.resize(self.buckets.len());
+    }
+
+    pub fn set_rehash_hook(&mut self, f: impl FnMut(usize, usize) + Send + 'static) {
+        self.on_rehash = Some(Mutex::new(Box::new(f)));
     }
 
     fn rehash(&mut self) {
-        let mut new_table = HashMap::with_exact_capacity(self.buckets.len() * 2, self.load_factor);
-        for bucket in self.buckets.iter_mut() {
-            if let Some(entry) = bucket.take() {
-                new_table.insert(entry.key, entry.value);
-                let mut current = entry.next;
-                while let Some(entry) = current {
-                    new_table.insert(entry.key, entry.value);
-                    current = entry.next;
+        self.resize(scaled_capacity(self.buckets.len(), 2));
+    }
+
+    fn resize(&mut self, bucket_count: usize) {
+        let old_buckets =
+            mem::replace(&mut self.buckets, (0..bucket_count).map(|_| None).collect());
+        let old_bucket_count = old_buckets.len();
+        self.reset_bloom_filter();
+        for mut entry in old_buckets.into_iter().flatten() {
+            loop {
+                let next = entry.next.take();
+                self.push_front(entry);
+                match next {
+                    Some(next) => entry = *next,
+                    None => break,
                 }
             }
         }
-        mem::swap(self, &mut new_table);
+        if let Some(on_rehash) = &mut self.on_rehash {
+            on_rehash.get_mut().unwrap_or_else(PoisonError::into_inner)(
+                old_bucket_count,
+                bucket_count,
+            );
+        }
     }
 
     pub fn get(&self, key: &K) -> Option<&V> {
         if self.is_empty() {
             return None;
         }
-        let hash = Self::calculate_hash(key);
+        if let Some(memo) = &self.lookup_memo {
+            return self.get_memoized(memo, key);
+        }
+        let hash = Self::calculate_hash(key, self.seed);
+        if let Some(filter) = &self.bloom_filter {
+            if !filter.may_contain(hash) {
+                return None;
+            }
+        }
         let index = hash as usize % self.buckets.len();
         match self.buckets.get(index) {
             Some(option) => {
@@ -146,11 +503,41 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
         }
     }
 
+    fn get_memoized(&self, memo: &LookupMemo, key: &K) -> Option<&V> {
+        if let Some(node) = self.memoized_node(key) {
+            return Some(&node.value);
+        }
+        let hash = Self::calculate_hash(key, self.seed);
+        if let Some(filter) = &self.bloom_filter {
+            if !filter.may_contain(hash) {
+                return None;
+            }
+        }
+        let index = hash as usize % self.buckets.len();
+        let (position, node) =
+            iter::successors(self.buckets[index].as_ref(), |node| node.next.as_deref())
+                .enumerate()
+                .find(|(_, node)| node.key == *key)?;
+        memo.store(index, position);
+        Some(&node.value)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn get_copied(&self, key: &K) -> Option<V>
+    where
+        V: Copy,
+    {
+        self.get(key).copied()
+    }
+
     pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
         if self.is_empty() {
             return None;
         }
-        let hash = Self::calculate_hash(key);
+        let hash = Self::calculate_hash(key, self.seed);
         let index = hash as usize % self.buckets.len();
         match self.buckets.get_mut(index) {
             Some(option) => {
@@ -187,11 +574,83 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
         }
     }
 
+    pub fn get_key_value_mut(&mut self, key: &K) -> Option<(&K, &mut V)> {
+        if self.is_empty() {
+            return None;
+        }
+        let index = Self::calculate_hash(key, self.seed) as usize % self.buckets.len();
+        let mut current = self.buckets[index].as_mut();
+        while let Some(Node {
+            key: stored,
+            value,
+            next,
+        }) = current
+        {
+            if *stored == *key {
+                return Some((&*stored, value));
+            }
+            current = next.as_deref_mut();
+        }
+        None
+    }
+
+    // Destructuring each node hands out disjoint borrows of its fields, so both values can be
+    // borrowed from the same chain without unsafe code
+    pub fn get_pair_mut(&mut self, a: &K, b: &K) -> (Option<&mut V>, Option<&mut V>) {
+        debug_assert!(a != b, "get_pair_mut requires distinct keys");
+        if self.is_empty() {
+            return (None, None);
+        }
+        let index_a = Self::calculate_hash(a, self.seed) as usize % self.buckets.len();
+        let index_b = Self::calculate_hash(b, self.seed) as usize % self.buckets.len();
+        if index_a == index_b {
+            let mut found = (None, None);
+            let mut current = self.buckets[index_a].as_mut();
+            while let Some(Node { key, value, next }) = current {
+                if *key == *a {
+                    found.0 = Some(value);
+                } else if *key == *b {
+                    found.1 = Some(value);
+                }
+                current = next.as_deref_mut();
+            }
+            return found;
+        }
+        let (low, high) = self.buckets.split_at_mut(index_a.max(index_b));
+        let (first, second) = (&mut low[index_a.min(index_b)], &mut high[0]);
+        let (bucket_a, bucket_b) = if index_a < index_b {
+            (first, second)
+        } else {
+            (second, first)
+        };
+        (
+            Self::chain_value_mut(bucket_a, a),
+            Self::chain_value_mut(bucket_b, b),
+        )
+    }
+
+    fn chain_value_mut<'a>(bucket: &'a mut Option<Node<K, V>>, key: &K) -> Option<&'a mut V> {
+        let mut current = bucket.as_mut();
+        while let Some(node) = current {
+            if node.key == *key {
+                return Some(&mut node.value);
+            }
+            current = node.next.as_deref_mut();
+        }
+        None
+    }
+
+    // Unlike insert, an absent key is left absent
+    pub fn replace_value(&mut self, key: &K, value: V) -> Option<V> {
+        self.get_mut(key)
+            .map(|current| mem::replace(current, value))
+    }
+
     pub fn remove(&mut self, key: &K) -> Option<V> {
         if self.is_empty() {
             return None;
         }
-        let hash = Self::calculate_hash(key);
+        let hash = Self::calculate_hash(key, self.seed);
         let index = hash as usize % self.buckets.len();
         match self.buckets.get_mut(index) {
             Some(option) => {
@@ -212,8 +671,8 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
                                 Some(result.value)
                             }
                             // First bucket is a miss and has next
-                            Some(miss) => {
-                                let mut current = &mut miss.next;
+                            Some(_) => {
+                                let mut current = &mut bucket.next;
                                 loop {
                                     match current {
                                         // Entry located
@@ -245,6 +704,326 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
             }
         }
     }
+
+    pub fn retain_until<F: FnMut(&K, &mut V) -> RetainAction>(&mut self, mut f: F) {
+        for bucket in self.buckets.iter_mut() {
+            while let Some(head) = bucket.as_mut() {
+                match f(&head.key, &mut head.value) {
+                    RetainAction::Keep => break,
+                    RetainAction::Remove => {
+                        *bucket = bucket.take().unwrap().next.map(|next| *next);
+                        self.length -= 1;
+                    }
+                    RetainAction::Stop => return,
+                }
+            }
+            let Some(head) = bucket.as_mut() else {
+                continue;
+            };
+            let mut current = &mut head.next;
+            while let Some(action) = current.as_mut().map(|node| f(&node.key, &mut node.value)) {
+                match action {
+                    RetainAction::Keep => current = &mut current.as_mut().unwrap().next,
+                    RetainAction::Remove => {
+                        let mut removed = current.take().unwrap();
+                        *current = removed.next.take();
+                        self.length -= 1;
+                    }
+                    RetainAction::Stop => return,
+                }
+            }
+        }
+    }
+
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V> {
+        match self.locate(key) {
+            Ok(entry) => Entry::Vacant(entry),
+            Err((entry, _)) => Entry::Occupied(entry),
+        }
+    }
+
+    // Like entry, but an occupied entry hands the key back instead of dropping it
+    fn locate(&mut self, key: K) -> Result<VacantEntry<'_, K, V>, (OccupiedEntry<'_, K, V>, K)> {
+        self.grow_if_needed();
+        let hash = Self::calculate_hash(&key, self.seed);
+        let index = hash as usize % self.buckets.len();
+        let length = &mut self.length;
+        let bucket = &mut self.buckets[index];
+        match Self::chain_position(bucket, &key) {
+            None => {
+                // Set before the insert happens, a vacant entry that is dropped only leaves
+                // a false positive behind
+                if let Some(filter) = &mut self.bloom_filter {
+                    filter.insert(hash);
+                }
+                Ok(VacantEntry {
+                    key,
+                    bucket,
+                    length,
+                })
+            }
+            Some(position) => Err((Self::occupied_at(bucket, position, length), key)),
+        }
+    }
+
+    // Absent keys are skipped, the pairs come back in the order of their keys
+    pub fn remove_all<'a, I: IntoIterator<Item = &'a K>>(&mut self, keys: I) -> Vec<(K, V)>
+    where
+        K: 'a,
+    {
+        keys.into_iter()
+            .filter_map(|key| self.find_occupied(key).map(OccupiedEntry::remove_entry))
+            .collect()
+    }
+
+    fn find_occupied(&mut self, key: &K) -> Option<OccupiedEntry<'_, K, V>> {
+        if self.is_empty() {
+            return None;
+        }
+        let hash = Self::calculate_hash(key, self.seed);
+        let index = hash as usize % self.buckets.len();
+        let bucket = &mut self.buckets[index];
+        let position = Self::chain_position(bucket, key)?;
+        Some(Self::occupied_at(bucket, position, &mut self.length))
+    }
+
+    fn occupied_at<'a>(
+        bucket: &'a mut Option<Node<K, V>>,
+        position: usize,
+        length: &'a mut usize,
+    ) -> OccupiedEntry<'a, K, V> {
+        if position == 0 {
+            return OccupiedEntry {
+                slot: Slot::Head(bucket),
+                length,
+            };
+        }
+        let mut link = &mut bucket.as_mut().unwrap().next;
+        for _ in 1..position {
+            link = &mut link.as_mut().unwrap().next;
+        }
+        OccupiedEntry {
+            slot: Slot::Link(link),
+            length,
+        }
+    }
+
+    fn chain_position(bucket: &Option<Node<K, V>>, key: &K) -> Option<usize> {
+        let mut current = bucket.as_ref();
+        let mut position = 0;
+        while let Some(node) = current {
+            if node.key == *key {
+                return Some(position);
+            }
+            current = node.next.as_deref();
+            position += 1;
+        }
+        None
+    }
+
+    pub fn merge_with<F: FnMut(&K, &mut V, V)>(&mut self, other: Self, mut resolve: F) {
+        self.reserve(other.len());
+        for mut entry in other.buckets.into_iter().flatten() {
+            loop {
+                let next = entry.next.take();
+                match self.get_mut(&entry.key) {
+                    Some(existing) => resolve(&entry.key, existing, entry.value),
+                    None => {
+                        self.insert(entry.key, entry.value);
+                    }
+                }
+                match next {
+                    Some(next) => entry = *next,
+                    None => break,
+                }
+            }
+        }
+    }
+
+    #[cfg(any(test, feature = "invariant-checks"))]
+    pub fn verify_invariants(&self) {
+        let mut count = 0;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            let mut current = bucket.as_ref();
+            while let Some(node) = current {
+                assert_eq!(
+                    Self::calculate_hash(&node.key, self.seed) as usize % self.buckets.len(),
+                    index,
+                    "node is chained in the wrong bucket"
+                );
+                count += 1;
+                current = node.next.as_deref();
+            }
+        }
+        assert_eq!(count, self.length, "length does not match the node count");
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<K: Hash + Eq + Send, V: Send> HashMap<K, V> {
+    pub fn par_from_iter<I: IntoParallelIterator<Item = (K, V)>>(iter: I) -> Self {
+        iter.into_par_iter()
+            .fold(Self::new, |mut table, (key, value)| {
+                table.insert(key, value);
+                table
+            })
+            .reduce(Self::new, |mut left, mut right| {
+                // Merge the smaller shard into the larger one, the right shard holds later values
+                if left.len() >= right.len() {
+                    left.merge_with(right, |_, existing, incoming| *existing = incoming);
+                    left
+                } else {
+                    right.merge_with(left, |_, _, _| {});
+                    right
+                }
+            })
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<K: Hash + Eq + Send, V: Send> IntoParallelIterator for HashMap<K, V> {
+    type Iter = IntoParIter<K, V>;
+    type Item = (K, V);
+
+    fn into_par_iter(self) -> Self::Iter {
+        IntoParIter {
+            buckets: self.buckets,
+        }
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<K: Hash + Eq + Send, V: Send> ParallelIterator for IntoParIter<K, V> {
+    type Item = (K, V);
+
+    fn drive_unindexed<C: UnindexedConsumer<Self::Item>>(self, consumer: C) -> C::Result {
+        // Rayon splits the bucket Vec into ranges, each one drains the chains of its buckets
+        self.buckets
+            .into_par_iter()
+            .flat_map_iter(|mut current| {
+                std::iter::from_fn(move || {
+                    let node = current.take()?;
+                    current = node.next.map(|next| *next);
+                    Some((node.key, node.value))
+                })
+            })
+            .drive_unindexed(consumer)
+    }
+}
+
+impl<'a, K: Hash + Eq, V> Entry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        self.or_insert_with_key(|_| default())
+    }
+    pub fn or_insert_with_key<F: FnOnce(&K) -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let value = default(entry.key());
+                entry.insert(value)
+            }
+        }
+    }
+    pub fn or_default(self) -> &'a mut V
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+    pub fn and_modify<F: FnOnce(&mut V)>(mut self, f: F) -> Self {
+        if let Entry::Occupied(entry) = &mut self {
+            f(entry.get_mut());
+        }
+        self
+    }
+}
+
+impl<'a, K: Hash + Eq, V> OccupiedEntry<'a, K, V> {
+    fn node(&self) -> &Node<K, V> {
+        match &self.slot {
+            Slot::Head(bucket) => bucket.as_ref().unwrap(),
+            Slot::Link(link) => link.as_deref().unwrap(),
+        }
+    }
+    fn node_mut(&mut self) -> &mut Node<K, V> {
+        match &mut self.slot {
+            Slot::Head(bucket) => bucket.as_mut().unwrap(),
+            Slot::Link(link) => link.as_deref_mut().unwrap(),
+        }
+    }
+    pub fn key(&self) -> &K {
+        &self.node().key
+    }
+    pub fn get(&self) -> &V {
+        &self.node().value
+    }
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.node_mut().value
+    }
+    pub fn into_mut(self) -> &'a mut V {
+        match self.slot {
+            Slot::Head(bucket) => &mut bucket.as_mut().unwrap().value,
+            Slot::Link(link) => &mut link.as_deref_mut().unwrap().value,
+        }
+    }
+    pub fn insert(&mut self, value: V) -> V {
+        mem::replace(self.get_mut(), value)
+    }
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+    pub fn remove_entry(self) -> (K, V) {
+        *self.length -= 1;
+        // Unlink the node by pulling its successor into its place
+        let node = match self.slot {
+            Slot::Head(bucket) => {
+                let mut node = bucket.take().unwrap();
+                *bucket = node.next.take().map(|next| *next);
+                node
+            }
+            Slot::Link(link) => {
+                let mut node = link.take().unwrap();
+                *link = node.next.take();
+                *node
+            }
+        };
+        (node.key, node.value)
+    }
+}
+
+impl<'a, K: Hash + Eq, V> VacantEntry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+    pub fn into_key(self) -> K {
+        self.key
+    }
+    pub fn insert(self, value: V) -> &'a mut V {
+        *self.length += 1;
+        let node = Node {
+            key: self.key,
+            value,
+            next: None,
+        };
+        let head = match self.bucket {
+            Some(head) => {
+                let next = mem::replace(head, node);
+                head.next = Some(Box::new(next));
+                head
+            }
+            bucket @ None => bucket.insert(node),
+        };
+        &mut head.value
+    }
 }
 
 impl<K: Hash + Eq, V> Default for HashMap<K, V> {
@@ -253,6 +1032,24 @@ impl<K: Hash + Eq, V> Default for HashMap<K, V> {
     }
 }
 
+impl<K: Hash + Eq, V> Extend<(K, V)> for HashMap<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> FromIterator<(K, V)> for HashMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut table = Self::new();
+        table.extend(iter);
+        table
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -468,6 +1265,22 @@ mod tests {
         assert_eq!(table.remove(&1), None);
     }
 
+    #[test]
+    fn test_remove_second_in_chain() {
+        let mut table = HashMap::with_exact_capacity(1, 8.0);
+        table.insert(1, "one");
+        table.insert(2, "two");
+        table.insert(3, "three");
+        assert_eq!(table.remove(&2), Some("two"));
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get(&2), None);
+        assert_eq!(table.insert(1, "new_one"), Some("one"));
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.remove(&3), Some("three"));
+        assert_eq!(table.remove(&1), Some("new_one"));
+        assert!(table.is_empty());
+    }
+
     #[test]
     fn test_clear() {
         let mut table = HashMap::new();
@@ -479,4 +1292,836 @@ mod tests {
         assert_eq!(table.get(&1), None);
         assert_eq!(table.get(&2), None);
     }
+
+    #[test]
+    fn test_merge_with() {
+        let mut counts = HashMap::new();
+        counts.insert("apple", 3);
+        counts.insert("banana", 1);
+        let mut other = HashMap::new();
+        other.insert("banana", 4);
+        other.insert("cherry", 2);
+        counts.merge_with(other, |_, existing, incoming| *existing += incoming);
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts.get(&"apple"), Some(&3));
+        assert_eq!(counts.get(&"banana"), Some(&5));
+        assert_eq!(counts.get(&"cherry"), Some(&2));
+    }
+
+    #[test]
+    fn test_merge_with_collisions() {
+        let mut table = HashMap::with_exact_capacity(2, 8.0);
+        let mut other = HashMap::with_exact_capacity(2, 8.0);
+        for i in 0..10 {
+            table.insert(i, i);
+            other.insert(i + 5, 100);
+        }
+        table.merge_with(other, |key, existing, incoming| *existing = *key + incoming);
+        assert_eq!(table.len(), 15);
+        for i in 0..5 {
+            assert_eq!(table.get(&i), Some(&i));
+        }
+        for i in 5..10 {
+            assert_eq!(table.get(&i), Some(&(i + 100)));
+        }
+        for i in 10..15 {
+            assert_eq!(table.get(&i), Some(&100));
+        }
+    }
+
+    #[test]
+    fn test_from_iter() {
+        let table: HashMap<i32, i32> = (0..100_000).map(|i| (i, i)).collect();
+        assert_eq!(table.len(), 100_000);
+        for i in 0..100_000 {
+            assert_eq!(table.get(&i), Some(&i));
+        }
+        // Sized once from the size hint, never rehashed
+        assert_eq!(table.buckets.len(), (100_000_f64 / 0.4) as usize + 1);
+    }
+
+    #[test]
+    fn test_extend() {
+        let mut table = HashMap::new();
+        table.insert(-1, -1);
+        table.extend((0..1000).map(|i| (i, i * 2)));
+        let bucket_count = table.buckets.len();
+        assert_eq!(table.len(), 1001);
+        assert_eq!(table.get(&-1), Some(&-1));
+        for i in 0..1000 {
+            assert_eq!(table.get(&i), Some(&(i * 2)));
+        }
+        table.reserve(0);
+        assert_eq!(table.buckets.len(), bucket_count);
+    }
+
+    #[test]
+    fn test_reserve() {
+        let mut table = HashMap::new();
+        table.reserve(10_000);
+        let bucket_count = table.buckets.len();
+        for i in 0..10_000 {
+            table.insert(i, i);
+        }
+        assert_eq!(table.buckets.len(), bucket_count);
+        assert_eq!(table.len(), 10_000);
+    }
+
+    #[test]
+    fn test_get_copied() {
+        let mut table = HashMap::new();
+        for i in 0..100_u64 {
+            table.insert(i, i * 3);
+        }
+        for i in 0..200_u64 {
+            assert_eq!(table.get_copied(&i), table.get(&i).copied());
+        }
+        assert_eq!(table.get_copied(&7), Some(21));
+        assert_eq!(table.get_copied(&100), None);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_from_iter() {
+        let parallel =
+            HashMap::par_from_iter((0..1_000_000_u64).into_par_iter().map(|i| (i, i * 2)));
+        let sequential: HashMap<u64, u64> = (0..1_000_000_u64).map(|i| (i, i * 2)).collect();
+        assert_eq!(parallel.len(), sequential.len());
+        for i in 0..1_000_000 {
+            assert_eq!(parallel.get(&i), sequential.get(&i));
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_from_iter_duplicates() {
+        let table = HashMap::par_from_iter((0..100_000_u64).into_par_iter().map(|i| (i % 100, i)));
+        assert_eq!(table.len(), 100);
+        for i in 0..100 {
+            assert_eq!(table.get(&i), Some(&(99_900 + i)));
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_into_par_iter() {
+        let table: HashMap<u64, u64> = (0..100_000_u64).map(|i| (i, i * 2)).collect();
+        let sequential: u64 = (0..100_000_u64).map(|i| i + i * 2).sum();
+        assert_eq!(
+            table.into_par_iter().map(|(k, v)| k + v).sum::<u64>(),
+            sequential
+        );
+
+        let mut table = HashMap::with_exact_capacity(4, 64.0);
+        for i in 0..100 {
+            table.insert(i.to_string(), i);
+        }
+        let mut entries: Vec<(String, i32)> = table.into_par_iter().collect();
+        entries.sort_by_key(|(_, value)| *value);
+        assert_eq!(
+            entries,
+            (0..100).map(|i| (i.to_string(), i)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_bucket_counts() {
+        let mut table = HashMap::with_exact_capacity(1, 8.0);
+        assert_eq!(table.occupied_bucket_count(), 0);
+        assert_eq!(table.empty_bucket_count(), 1);
+        table.insert(1, "one");
+        table.insert(2, "two");
+        table.insert(3, "three");
+        assert_eq!(table.occupied_bucket_count(), 1);
+        assert_eq!(table.empty_bucket_count(), 0);
+
+        let mut table = HashMap::with_exact_capacity(16, 8.0);
+        let seed = table.seed;
+        for i in 0..64 {
+            table.insert(i, i);
+        }
+        let occupied = (0..64)
+            .map(|i| HashMap::<i32, i32>::calculate_hash(&i, seed) as usize % 16)
+            .collect::<std::collections::HashSet<_>>()
+            .len();
+        assert_eq!(table.occupied_bucket_count(), occupied);
+        assert_eq!(table.empty_bucket_count(), 16 - occupied);
+        table.clear();
+        assert_eq!(table.occupied_bucket_count(), 0);
+        assert_eq!(table.empty_bucket_count(), 16);
+    }
+
+    #[test]
+    fn test_insert_tracked() {
+        let mut table = HashMap::with_load_factor(0.5);
+        for i in 0..1000_usize {
+            let (old, rehashed) = table.insert_tracked(i, i);
+            assert_eq!(old, None);
+            assert_eq!(rehashed, i >= 32 && i.is_power_of_two());
+        }
+        assert_eq!(table.insert_tracked(0, 1), (Some(0), false));
+    }
+
+    #[test]
+    fn test_entry() {
+        let mut table = HashMap::new();
+        *table.entry("a").or_insert(0) += 1;
+        *table.entry("a").or_insert(0) += 1;
+        *table.entry("b").or_default() += 5;
+        assert_eq!(table.entry("c").key(), &"c");
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get(&"a"), Some(&2));
+        assert_eq!(table.get(&"b"), Some(&5));
+        match table.entry("a") {
+            Entry::Occupied(entry) => {
+                assert_eq!(entry.key(), &"a");
+                assert_eq!(entry.get(), &2);
+            }
+            Entry::Vacant(_) => panic!("entry should be occupied"),
+        }
+        match table.entry("c") {
+            Entry::Occupied(_) => panic!("entry should be vacant"),
+            Entry::Vacant(entry) => assert_eq!(entry.into_key(), "c"),
+        }
+        assert_eq!(table.len(), 2);
+    }
+
+    #[test]
+    fn test_entry_and_modify_or_insert_with_key() {
+        let mut table = HashMap::with_exact_capacity(1, 8.0);
+        for i in 0..4 {
+            table.insert(i, i * 10);
+        }
+        for i in 0..8 {
+            table
+                .entry(i)
+                .and_modify(|value| *value += 1)
+                .or_insert_with_key(|key| key * 100);
+        }
+        assert_eq!(table.len(), 8);
+        for i in 0..4 {
+            assert_eq!(table.get(&i), Some(&(i * 10 + 1)));
+        }
+        for i in 4..8 {
+            assert_eq!(table.get(&i), Some(&(i * 100)));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn test_rehash_capacity_overflow() {
+        scaled_capacity(usize::MAX / 2 + 1, 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn test_reserve_capacity_overflow() {
+        let mut table = HashMap::<u8, u8>::new();
+        table.insert(1, 1);
+        table.reserve(usize::MAX / 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn test_with_capacity_overflow() {
+        HashMap::<u8, u8>::with_capacity(usize::MAX / 4);
+    }
+
+    #[test]
+    fn test_load_factor() {
+        let table: HashMap<i32, i32> = HashMap::with_load_factor(0.7);
+        assert_eq!(table.load_factor(), 0.7);
+        assert_eq!(table.fill_factor(), 0.0);
+    }
+
+    #[test]
+    fn test_try_insert() {
+        let mut table = HashMap::new();
+        assert_eq!(table.try_insert(1, "one").unwrap(), &mut "one");
+        *table.try_insert(2, "two").unwrap() = "second";
+        let error = table.try_insert(1, "uno").unwrap_err();
+        assert_eq!(error.key, 1);
+        assert_eq!(error.value, "uno");
+        assert_eq!(error.current, &mut "one");
+        *error.current = "first";
+        assert_eq!(table.get(&1), Some(&"first"));
+        assert_eq!(table.get(&2), Some(&"second"));
+        assert_eq!(table.len(), 2);
+
+        let mut table = HashMap::new();
+        for i in 0..1000 {
+            assert_eq!(table.try_insert(i, i.to_string()).unwrap(), &i.to_string());
+        }
+        assert_eq!(table.len(), 1000);
+        for i in 0..1000 {
+            assert_eq!(table.get(&i), Some(&i.to_string()));
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    struct CaseInsensitive(&'static str);
+
+    impl PartialEq for CaseInsensitive {
+        fn eq(&self, other: &Self) -> bool {
+            self.0.eq_ignore_ascii_case(other.0)
+        }
+    }
+
+    impl Eq for CaseInsensitive {}
+
+    impl Hash for CaseInsensitive {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.0.to_ascii_lowercase().hash(state);
+        }
+    }
+
+    #[test]
+    fn test_overwrite_keeps_new_key() {
+        let mut table = HashMap::new();
+        assert_eq!(table.insert(CaseInsensitive("Foo"), 1), None);
+        assert_eq!(table.insert(CaseInsensitive("foo"), 2), Some(1));
+        assert_eq!(table.len(), 1);
+        match table.entry(CaseInsensitive("FOO")) {
+            Entry::Occupied(entry) => assert_eq!(entry.key().0, "foo"),
+            Entry::Vacant(_) => panic!("key is missing"),
+        }
+    }
+
+    #[test]
+    fn test_replace() {
+        let mut table = HashMap::with_exact_capacity(2, 8.0);
+        for i in 0..10 {
+            assert_eq!(table.replace(i, i), None);
+        }
+        for i in 0..10 {
+            assert_eq!(table.replace(i, i * 10), Some(i));
+        }
+        assert_eq!(table.len(), 10);
+        for i in 0..10 {
+            assert_eq!(table.get(&i), Some(&(i * 10)));
+        }
+    }
+
+    #[test]
+    fn test_get_pair_mut() {
+        let mut table = HashMap::with_exact_capacity(16, 8.0);
+        let seed = table.seed;
+        let bucket = |key: &i32| HashMap::<i32, i32>::calculate_hash(key, seed) as usize % 16;
+        let same = (1..).find(|key| bucket(key) == bucket(&0)).unwrap();
+        let other = (1..).find(|key| bucket(key) != bucket(&0)).unwrap();
+        for i in 0..64 {
+            table.insert(i, i * 10);
+        }
+        table.insert(same, same * 10);
+        table.insert(other, other * 10);
+
+        let (a, b) = table.get_pair_mut(&0, &same);
+        mem::swap(a.unwrap(), b.unwrap());
+        assert_eq!(table.get(&0), Some(&(same * 10)));
+        assert_eq!(table.get(&same), Some(&0));
+
+        let (a, b) = table.get_pair_mut(&other, &0);
+        mem::swap(a.unwrap(), b.unwrap());
+        assert_eq!(table.get(&0), Some(&(other * 10)));
+        assert_eq!(table.get(&other), Some(&(same * 10)));
+
+        let (a, b) = table.get_pair_mut(&0, &-1);
+        assert_eq!(a, Some(&mut (other * 10)));
+        assert_eq!(b, None);
+        assert_eq!(
+            HashMap::<i32, i32>::new().get_pair_mut(&1, &2),
+            (None, None)
+        );
+    }
+
+    #[test]
+    fn test_insert_counts() {
+        let mut table = HashMap::new();
+        for i in 0..100 {
+            table.insert(i, i);
+        }
+        for i in 0..10 {
+            table.insert(i, i + 1);
+        }
+        assert_eq!(table.new_key_count(), 100);
+        assert_eq!(table.overwrite_count(), 10);
+        table.clear();
+        assert_eq!(table.new_key_count(), 0);
+        assert_eq!(table.overwrite_count(), 0);
+    }
+
+    #[test]
+    fn test_insert_counts_broken_eq() {
+        // Eq only looks at the low byte, so distinct keys compare equal
+        struct LossyKey(u64);
+        impl Hash for LossyKey {
+            fn hash<H: Hasher>(&self, state: &mut H) {
+                state.write_u8(0);
+            }
+        }
+        impl PartialEq for LossyKey {
+            fn eq(&self, other: &Self) -> bool {
+                self.0 as u8 == other.0 as u8
+            }
+        }
+        impl Eq for LossyKey {}
+
+        let mut table = HashMap::new();
+        for i in 0..1000 {
+            table.insert(LossyKey(i), i);
+        }
+        assert_eq!(table.new_key_count(), 256);
+        assert_eq!(table.overwrite_count(), 744);
+        assert_eq!(table.len(), 256);
+    }
+
+    #[test]
+    fn test_replace_value() {
+        let mut table = HashMap::new();
+        assert_eq!(table.replace_value(&1, 10), None);
+        assert_eq!(table.get(&1), None);
+        for i in 0..100 {
+            table.insert(i, i);
+        }
+        for i in 0..100 {
+            assert_eq!(table.replace_value(&i, i + 1000), Some(i));
+        }
+        assert_eq!(table.replace_value(&100, 0), None);
+        assert_eq!(table.get(&100), None);
+        assert_eq!(table.len(), 100);
+        for i in 0..100 {
+            assert_eq!(table.get(&i), Some(&(i + 1000)));
+        }
+    }
+
+    #[test]
+    fn test_verify_invariants() {
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+        let mut table = HashMap::new();
+        table.verify_invariants();
+        for round in 0..20_000 {
+            let key = rng.gen_range(0..500);
+            if rng.gen_bool(0.6) {
+                table.insert(key, round);
+            } else {
+                table.remove(&key);
+            }
+            if round % 1000 == 0 {
+                table.verify_invariants();
+            }
+        }
+        table.verify_invariants();
+        table.clear();
+        table.verify_invariants();
+    }
+
+    #[test]
+    fn test_from_pairs_checked() {
+        let table = HashMap::from_pairs_checked((0..1000).map(|i| (i, i * 2))).unwrap();
+        assert_eq!(table.len(), 1000);
+        for i in 0..1000 {
+            assert_eq!(table.get(&i), Some(&(i * 2)));
+        }
+        let pairs = (0..1000).map(|i| (i, i)).chain([(500, 0), (600, 0)]);
+        match HashMap::from_pairs_checked(pairs) {
+            Err(error) => assert_eq!(error.key, 500),
+            Ok(_) => panic!("duplicate key was not detected"),
+        }
+        assert!(HashMap::<i32, i32>::from_pairs_checked([])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_get_key_value_mut() {
+        // Only the id takes part in Hash and Eq
+        #[derive(Debug)]
+        struct Tagged {
+            id: i32,
+            tag: &'static str,
+        }
+        impl Hash for Tagged {
+            fn hash<H: Hasher>(&self, state: &mut H) {
+                self.id.hash(state);
+            }
+        }
+        impl PartialEq for Tagged {
+            fn eq(&self, other: &Self) -> bool {
+                self.id == other.id
+            }
+        }
+        impl Eq for Tagged {}
+
+        let mut table = HashMap::new();
+        for id in 0..100 {
+            table.insert(Tagged { id, tag: "stored" }, id);
+        }
+        let lookup = Tagged {
+            id: 42,
+            tag: "lookup",
+        };
+        let (key, value) = table.get_key_value_mut(&lookup).unwrap();
+        assert_eq!(key.tag, "stored");
+        assert_eq!(key.id, 42);
+        *value += 1000;
+        assert_eq!(table.get(&lookup), Some(&1042));
+        assert!(table
+            .get_key_value_mut(&Tagged { id: 100, tag: "" })
+            .is_none());
+        assert!(HashMap::<i32, i32>::new().get_key_value_mut(&1).is_none());
+    }
+
+    #[test]
+    fn test_shrink_to() {
+        let mut table = HashMap::new();
+        for i in 0..10_000 {
+            table.insert(i, i);
+        }
+        for i in 50..10_000 {
+            table.remove(&i);
+        }
+        let grown = table.buckets.len();
+        table.shrink_to(1000);
+        let bucket_count = table.buckets.len();
+        assert!(bucket_count < grown);
+        assert!(bucket_count >= (1000.0 / table.load_factor()) as usize);
+        assert!(bucket_count < (1100.0 / table.load_factor()) as usize);
+        for i in 0..50 {
+            assert_eq!(table.get(&i), Some(&i));
+        }
+        for i in 50..1000 {
+            table.insert(i, i);
+        }
+        assert_eq!(table.buckets.len(), bucket_count);
+
+        table.shrink_to(10_000);
+        assert_eq!(table.buckets.len(), bucket_count);
+    }
+
+    #[test]
+    fn test_rehash_hook() {
+        use std::sync::{Arc, Mutex};
+
+        let rehashes = Arc::new(Mutex::new(Vec::new()));
+        let mut table = HashMap::with_load_factor(0.5);
+        let recorded = rehashes.clone();
+        table.set_rehash_hook(move |old, new| recorded.lock().unwrap().push((old, new)));
+        for i in 0..1000 {
+            table.insert(i, i);
+        }
+        assert_eq!(
+            *rehashes.lock().unwrap(),
+            vec![(64, 128), (128, 256), (256, 512), (512, 1024), (1024, 2048)]
+        );
+        for i in 0..1000 {
+            assert_eq!(table.get(&i), Some(&i));
+        }
+    }
+
+    #[test]
+    fn test_rehash_hook_reports_every_resize() {
+        use std::sync::Arc;
+
+        let rehashes = Arc::new(Mutex::new(Vec::new()));
+        let mut table = HashMap::new();
+        let recorded = rehashes.clone();
+        table.set_rehash_hook(move |old, new| recorded.lock().unwrap().push((old, new)));
+        table.insert(0, 0);
+        table.reserve(100);
+        table.compact();
+        table.shrink_to(0);
+        assert_eq!(
+            *rehashes.lock().unwrap(),
+            vec![(64, 253), (253, 253), (253, 64)]
+        );
+        assert_eq!(table.get(&0), Some(&0));
+    }
+
+    #[test]
+    fn test_capacity_before_first_insert() {
+        let mut table = HashMap::new();
+        assert_eq!(table.capacity(), 0);
+        table.insert(1, 1);
+        assert!(table.capacity() > 0);
+        assert!(table.capacity() >= table.len());
+    }
+
+    #[test]
+    fn test_seeded_distribution() {
+        // Keys that all share one bucket of a 64 bucket table hashed with seed 0
+        let home = |key: &i32| HashMap::<i32, i32>::calculate_hash(key, 0) as usize % 64;
+        let keys: Vec<i32> = (0..).filter(|key| home(key) == home(&0)).take(20).collect();
+        let layout = |table: &HashMap<i32, i32>| {
+            keys.iter()
+                .map(|key| {
+                    HashMap::<i32, i32>::calculate_hash(key, table.seed) as usize
+                        % table.buckets.len()
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut tables = [
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::with_seed(0),
+            HashMap::with_seed(0),
+        ];
+        for table in tables.iter_mut() {
+            for &key in &keys {
+                table.insert(key, key);
+            }
+        }
+        assert_ne!(layout(&tables[0]), layout(&tables[1]));
+        assert!(layout(&tables[0]).iter().any(|&bucket| bucket != home(&0)));
+        assert_eq!(layout(&tables[2]), vec![home(&0); keys.len()]);
+        assert_eq!(layout(&tables[2]), layout(&tables[3]));
+        for table in &tables {
+            for key in &keys {
+                assert_eq!(table.get(key), Some(key));
+            }
+        }
+    }
+
+    #[test]
+    fn test_zero_sized_value_layout() {
+        use std::mem::size_of;
+
+        assert_eq!(
+            size_of::<Node<u64, ()>>(),
+            size_of::<(u64, Option<Box<Node<u64, ()>>>)>()
+        );
+        // An empty bucket reuses the niche of a key like String instead of a discriminant
+        assert_eq!(
+            size_of::<Option<Node<String, ()>>>(),
+            size_of::<Node<String, ()>>()
+        );
+    }
+
+    #[test]
+    fn test_insert_or_get() {
+        let mut table = HashMap::new();
+        assert_eq!(*table.insert_or_get("key", 1), 1);
+        assert_eq!(*table.insert_or_get("key", 2), 1);
+        assert_eq!(*table.insert_or_get("other", 3), 3);
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get(&"key"), Some(&1));
+    }
+
+    #[test]
+    fn test_retain_until_stops_early() {
+        let mut table = HashMap::new();
+        for i in 0..1000 {
+            table.insert(i, i);
+        }
+        let mut visited = Vec::new();
+        let mut removed = 0;
+        table.retain_until(|key, value| {
+            if removed == 100 {
+                return RetainAction::Stop;
+            }
+            visited.push(*key);
+            *value += 1;
+            if key % 2 == 0 {
+                removed += 1;
+                RetainAction::Remove
+            } else {
+                RetainAction::Keep
+            }
+        });
+        assert_eq!(removed, 100);
+        assert_eq!(table.len(), 900);
+        for i in 0..1000 {
+            match (visited.contains(&i), i % 2 == 0) {
+                (true, true) => assert_eq!(table.get(&i), None),
+                (true, false) => assert_eq!(table.get(&i), Some(&(i + 1))),
+                (false, _) => assert_eq!(table.get(&i), Some(&i)),
+            }
+        }
+        table.verify_invariants();
+    }
+
+    #[test]
+    fn test_occupied_entry_remove_entry() {
+        let mut table = HashMap::with_capacity(64);
+        for i in 0..1000 {
+            table.insert(i, i * 2);
+        }
+        for i in (0..1000).step_by(3) {
+            let removed = match table.entry(i) {
+                Entry::Occupied(o) => o.remove_entry(),
+                Entry::Vacant(_) => panic!("key {i} should be present"),
+            };
+            assert_eq!(removed, (i, i * 2));
+            assert_eq!(table.get(&i), None);
+        }
+        assert_eq!(table.len(), 1000 - 334);
+        for i in 0..1000 {
+            let expected = (i % 3 != 0).then_some(i * 2);
+            assert_eq!(table.get(&i).copied(), expected);
+        }
+        table.verify_invariants();
+    }
+
+    #[test]
+    fn test_occupied_entry_insert_and_remove() {
+        let mut table = HashMap::new();
+        table.insert("a", 1);
+        match table.entry("a") {
+            Entry::Occupied(mut o) => {
+                assert_eq!(o.insert(5), 1);
+                assert_eq!(*o.get(), 5);
+                assert_eq!(o.remove(), 5);
+            }
+            Entry::Vacant(_) => panic!("key should be present"),
+        }
+        assert!(table.is_empty());
+        assert_eq!(table.get(&"a"), None);
+        table.verify_invariants();
+    }
+
+    #[test]
+    fn test_compact_after_churn() {
+        let mut table = HashMap::new();
+        for round in 0..20 {
+            for i in 0..5000 {
+                table.insert(i, i + round);
+            }
+            for i in (round % 3..5000).step_by(3) {
+                table.remove(&i);
+            }
+        }
+        let expected: Vec<_> = (0..5000).map(|i| table.get(&i).copied()).collect();
+        let len = table.len();
+        let capacity = table.capacity();
+        table.compact();
+        assert_eq!(table.len(), len);
+        assert_eq!(table.capacity(), capacity);
+        for (i, value) in expected.into_iter().enumerate() {
+            assert_eq!(table.get(&(i as i32)).copied(), value);
+        }
+        table.verify_invariants();
+    }
+
+    #[test]
+    fn test_insert_all_counts_overwrites() {
+        let mut table = HashMap::new();
+        table.insert(-1, 0);
+        let source = (0..1000).chain((0..1000).step_by(4)).chain([-1]);
+        assert_eq!(table.insert_all(source.map(|i| (i, i))), 251);
+        assert_eq!(table.len(), 1001);
+        for i in -1..1000 {
+            assert_eq!(table.get(&i), Some(&i));
+        }
+        assert_eq!(table.insert_all(Vec::new()), 0);
+        table.verify_invariants();
+    }
+
+    #[test]
+    fn test_bloom_filter_has_no_false_negatives() {
+        let mut table = HashMap::new();
+        for i in 0..1000 {
+            table.insert(i, i);
+        }
+        table.set_bloom_filter(true);
+        for i in 1000..20_000 {
+            table.insert(i, i);
+        }
+        *table.entry(-1).or_default() = -1;
+        for i in (0..20_000).step_by(3) {
+            table.remove(&i);
+        }
+        for i in -1..20_000 {
+            let expected = (i % 3 != 0).then_some(i);
+            assert_eq!(table.get(&i).copied(), expected);
+            assert_eq!(table.contains_key(&i), expected.is_some());
+        }
+        let filter = table.bloom_filter.as_ref().unwrap();
+        let seed = table.seed;
+        let false_positives = (20_000..40_000)
+            .filter(|key| filter.may_contain(HashMap::<i32, i32>::calculate_hash(key, seed)))
+            .count();
+        assert!(false_positives < 5000, "{false_positives} false positives");
+
+        table.clear();
+        assert!(table
+            .bloom_filter
+            .as_ref()
+            .unwrap()
+            .words
+            .iter()
+            .all(|&word| word == 0));
+        for i in 0..100 {
+            table.insert(i, i);
+        }
+        table.compact();
+        for i in 0..100 {
+            assert_eq!(table.get(&i), Some(&i));
+        }
+        table.set_bloom_filter(false);
+        assert!(table.bloom_filter.is_none());
+        assert_eq!(table.get(&50), Some(&50));
+        table.verify_invariants();
+    }
+
+    #[test]
+    fn test_remove_all() {
+        let mut table = HashMap::with_capacity(64);
+        for i in 0..1000 {
+            table.insert(i, i.to_string());
+        }
+        let keys: Vec<_> = (900..1100).rev().chain([5, 5, -1]).collect();
+        let removed = table.remove_all(&keys);
+        let expected: Vec<_> = (900..1000)
+            .rev()
+            .chain([5])
+            .map(|i| (i, i.to_string()))
+            .collect();
+        assert_eq!(removed, expected);
+        assert_eq!(table.len(), 899);
+        for i in 0..1000 {
+            let expected = (i < 900 && i != 5).then(|| i.to_string());
+            assert_eq!(table.get(&i).cloned(), expected);
+        }
+        assert!(HashMap::<i32, i32>::new().remove_all(&[1, 2]).is_empty());
+        table.verify_invariants();
+    }
+
+    #[test]
+    fn test_lookup_memo() {
+        let mut table = HashMap::with_load_factor(4.0);
+        table.set_lookup_memo(true);
+        for i in 0..10_000 {
+            assert_eq!(table.insert(i, i), None);
+            assert_eq!(table.get(&i), Some(&i));
+        }
+        for _ in 0..100 {
+            *table.entry(7).or_default() += 1;
+            assert_eq!(table.insert(7, table.get(&7).unwrap() + 1), Some(8));
+            assert_eq!(table.get(&7), Some(&9));
+            table.insert(7, 7);
+        }
+        // Alternating keys, and keys whose chain changes between calls
+        for i in 0..10_000 {
+            assert_eq!(table.get(&i), Some(&i));
+            if i < 5_000 {
+                assert_eq!(table.get(&(9_999 - i)), Some(&(9_999 - i)));
+            }
+            assert_eq!(table.get(&(i + 10_000)), None);
+            assert_eq!(table.insert(i, i + 1), Some(i));
+            if i % 2 == 0 {
+                assert_eq!(table.remove(&i), Some(i + 1));
+                assert_eq!(table.get(&i), None);
+            }
+        }
+        table.compact();
+        for i in 0..10_000 {
+            let expected = (i % 2 == 1).then_some(i + 1);
+            assert_eq!(table.get(&i).copied(), expected);
+            assert_eq!(table.get(&i).copied(), expected);
+        }
+        assert_eq!(table.len(), 5_000);
+        table.set_lookup_memo(false);
+        assert_eq!(table.get(&1), Some(&2));
+        table.verify_invariants();
+    }
 }
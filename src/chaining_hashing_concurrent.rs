@@ -1,46 +1,127 @@
-use parking_lot::RwLock;
+use parking_lot::{
+    ArcRwLockWriteGuard, RawRwLock, RwLock, RwLockReadGuard, RwLockUpgradableReadGuard,
+};
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
-use std::mem;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering::SeqCst;
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 
+#[cfg(feature = "rayon")]
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use std::{iter, mem, vec};
+
+// Shared so an entry can keep its bucket locked without holding the table's read lock
+type Bucket<K, V> = Arc<RwLock<Option<Entry<K, V>>>>;
+
+// Send and Sync come from the fields, test_send_sync keeps them from silently going away
 pub struct HashMap<K: Hash + Eq, V> {
-    buckets: RwLock<Vec<RwLock<Option<Entry<K, V>>>>>,
+    buckets: RwLock<Vec<Bucket<K, V>>>,
     length: Arc<AtomicUsize>,
+    bucket_count: AtomicUsize,
     load_factor: f64,
+    auto_shrink: bool,
 }
 
 struct Entry<K: Hash + Eq, V> {
     key: K,
-    value: Arc<V>,
+    value: Value<V>,
     next: Option<Box<Entry<K, V>>>,
 }
 
+// Keeps the key's bucket locked for writing until dropped. A resize waits for that lock, so
+// calling back into the map from the same thread meanwhile can deadlock.
+pub struct OccupiedOrVacant<'a, K: Hash + Eq, V> {
+    bucket: ArcRwLockWriteGuard<RawRwLock, Option<Entry<K, V>>>,
+    map: &'a HashMap<K, V>,
+    key: K,
+    // Position of the entry holding an equal key in the chain, whether its value is live or not
+    depth: Option<usize>,
+}
+
+// Returned by iter_locked, holds the table's read lock until dropped
+pub struct IterLocked<'a, K: Hash + Eq, V> {
+    buckets: RwLockReadGuard<'a, Vec<Bucket<K, V>>>,
+    index: usize,
+    // Live entries of the last bucket read, copied out so its lock is not held across yields
+    chain: vec::IntoIter<(K, Arc<V>)>,
+}
+
+enum Value<V> {
+    Strong(Arc<V>),
+    Weak(Weak<V>),
+}
+
+impl<V> Value<V> {
+    fn upgrade(&self) -> Option<Arc<V>> {
+        match self {
+            Value::Strong(value) => Some(value.clone()),
+            Value::Weak(value) => value.upgrade(),
+        }
+    }
+    fn is_live(&self) -> bool {
+        match self {
+            Value::Strong(_) => true,
+            Value::Weak(value) => value.strong_count() > 0,
+        }
+    }
+    fn cloned(&self) -> Option<V>
+    where
+        V: Clone,
+    {
+        match self {
+            Value::Strong(value) => Some(V::clone(value)),
+            Value::Weak(value) => value.upgrade().map(|value| V::clone(&value)),
+        }
+    }
+    fn into_strong(self) -> Option<Arc<V>> {
+        match self {
+            Value::Strong(value) => Some(value),
+            Value::Weak(value) => value.upgrade(),
+        }
+    }
+}
+
+// Smallest bucket count that holds entries without crossing the load factor
+fn required_buckets(entries: usize, load_factor: f64) -> usize {
+    let bucket_count = (entries as f64 / load_factor).floor();
+    assert!(bucket_count < usize::MAX as f64, "capacity overflow");
+    bucket_count as usize + 1
+}
+
 impl<K: Hash + Eq, V> HashMap<K, V> {
     pub fn new() -> Self {
         Self::with_exact_capacity(0, 0.4)
     }
     pub fn with_capacity(capacity: usize) -> Self {
-        Self::with_exact_capacity(capacity * 8, 0.4)
+        Self::with_exact_capacity(required_buckets(capacity, 0.4), 0.4)
     }
     pub fn with_load_factor(load_factor: f64) -> Self {
         Self::with_exact_capacity(0, load_factor)
     }
     fn with_exact_capacity(capacity: usize, load_factor: f64) -> Self {
         Self {
-            buckets: RwLock::new((0..capacity).map(|_| RwLock::new(None)).collect()),
+            buckets: RwLock::new((0..capacity).map(|_| Arc::default()).collect()),
             length: Arc::new(AtomicUsize::new(0)),
+            bucket_count: AtomicUsize::new(capacity),
             load_factor,
+            auto_shrink: false,
         }
     }
+    // Entries whose weak value was dropped still count until get_weak prunes them or they are
+    // removed or overwritten, even though get already treats them as absent
     pub fn len(&self) -> usize {
         self.length.load(SeqCst)
     }
     pub fn is_empty(&self) -> bool {
         self.length.load(SeqCst) == 0
     }
+    pub fn capacity(&self) -> usize {
+        (self.buckets.read().len() as f64 * self.load_factor) as usize
+    }
+    pub fn load_factor(&self) -> f64 {
+        self.load_factor
+    }
     pub fn fill_factor(&self) -> f64 {
         let buckets = self.buckets.read();
         if buckets.is_empty() {
@@ -49,12 +130,25 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
             self.length.load(SeqCst) as f64 / buckets.len() as f64
         }
     }
+    pub fn approx_fill_factor(&self) -> f64 {
+        // Lock free, so a resize can land between the two loads and skew the result either
+        // way. shrink_if_sparse checks again under the write lock before acting on it.
+        let length = self.length.load(SeqCst);
+        let bucket_count = self.bucket_count.load(SeqCst);
+        if bucket_count == 0 {
+            0.0
+        } else {
+            length as f64 / bucket_count as f64
+        }
+    }
     pub fn clear(&self) {
-        let mut buckets = self.buckets.write();
-        self.length.store(0, SeqCst);
-        for element in buckets.iter_mut() {
-            *element = RwLock::new(None);
+        let buckets = self.buckets.write();
+        // An entry may still hold its bucket, so each one is locked and the length is only reset
+        // once the entries have seen their last change
+        for element in buckets.iter() {
+            *element.write() = None;
         }
+        self.length.store(0, SeqCst);
     }
 
     fn calculate_hash(key: &K) -> u64 {
@@ -64,37 +158,165 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
     }
 
     pub fn insert(&self, key: K, value: Arc<V>) -> Option<Arc<V>> {
-        if self.buckets.read().is_empty() {
-            let mut buckets = self.buckets.write();
-            if buckets.is_empty() {
-                *buckets = (0..64).map(|_| RwLock::new(None)).collect();
+        self.insert_value(key, Value::Strong(value))
+    }
+
+    // The map does not keep the value alive; once it is dropped elsewhere the entry reads as
+    // absent and is pruned by get_weak
+    pub fn insert_weak(&self, key: K, value: &Arc<V>) -> Option<Arc<V>> {
+        self.insert_value(key, Value::Weak(Arc::downgrade(value)))
+    }
+
+    // Reuses the Arc of an equal stored value, found by a full O(n) scan
+    pub fn insert_interned(&self, key: K, value: Arc<V>) -> Arc<V>
+    where
+        V: Eq,
+    {
+        let shared = self.find_value(&value).unwrap_or(value);
+        self.insert(key, shared.clone());
+        shared
+    }
+
+    // Keys whose weak value has been dropped are skipped, like get treats them as absent
+    pub fn snapshot_keys(&self) -> Vec<K>
+    where
+        K: Clone,
+    {
+        let buckets = self.buckets.read();
+        let mut keys = Vec::with_capacity(self.len());
+        for bucket in buckets.iter() {
+            let bucket = bucket.read();
+            let mut current = bucket.as_ref();
+            while let Some(entry) = current {
+                if entry.value.is_live() {
+                    keys.push(entry.key.clone());
+                }
+                current = entry.next.as_deref();
             }
         }
-        if self.fill_factor() >= self.load_factor {
-            self.rehash();
+        keys
+    }
+
+    // Unlike snapshot_keys nothing is collected up front, but the table's read lock is held until
+    // the iterator is dropped. Every entry present for the whole traversal is yielded once.
+    // The lock is fair, so once a resize waits for it, any other call on the map from the
+    // iterating thread deadlocks. No method may be called from that thread while it lives.
+    pub fn iter_locked(&self) -> IterLocked<'_, K, V>
+    where
+        K: Clone,
+    {
+        IterLocked {
+            buckets: self.buckets.read(),
+            index: 0,
+            chain: Vec::new().into_iter(),
         }
+    }
+
+    fn find_value(&self, value: &V) -> Option<Arc<V>>
+    where
+        V: Eq,
+    {
+        let buckets = self.buckets.read();
+        for bucket in buckets.iter() {
+            let bucket = bucket.read();
+            let mut current = bucket.as_ref();
+            while let Some(entry) = current {
+                match entry.value.upgrade() {
+                    Some(existing) if *existing == *value => return Some(existing),
+                    _ => current = entry.next.as_deref(),
+                }
+            }
+        }
+        None
+    }
+
+    fn insert_value(&self, key: K, value: Value<V>) -> Option<Arc<V>> {
+        self.insert_value_with(key, value, |stored, key| *stored = key)
+    }
+
+    // on_overwrite decides which of the stored and the new equal key is kept
+    fn insert_value_with(
+        &self,
+        key: K,
+        value: Value<V>,
+        on_overwrite: impl FnOnce(&mut K, K),
+    ) -> Option<Arc<V>> {
+        self.grow_if_needed();
         let buckets = self.buckets.read();
-        let old = HashMap::pre_locked_remove(&buckets, &self.length, &key);
         let hash = Self::calculate_hash(&key);
         let index = hash as usize % buckets.len();
-        let entry = Entry {
-            key,
-            value,
-            next: None,
-        };
-
-        let mut bucket = buckets[index].write();
-        match &mut *bucket {
-            Some(first_entry) => {
-                let next = mem::replace(first_entry, entry);
-                first_entry.next = Some(Box::new(next));
+        // Readers of the bucket are only blocked once the chain is actually modified
+        let bucket = buckets[index].upgradable_read();
+        let depth = iter::successors(bucket.as_ref(), |entry| entry.next.as_deref())
+            .position(|entry| entry.key == key);
+        let mut bucket = RwLockUpgradableReadGuard::upgrade(bucket);
+        match depth {
+            Some(depth) => {
+                let mut entry = bucket.as_mut().unwrap();
+                for _ in 0..depth {
+                    entry = entry.next.as_deref_mut().unwrap();
+                }
+                on_overwrite(&mut entry.key, key);
+                mem::replace(&mut entry.value, value).into_strong()
             }
             None => {
-                *bucket = Some(entry);
+                let next = bucket.take().map(Box::new);
+                *bucket = Some(Entry { key, value, next });
+                self.length.fetch_add(1, SeqCst);
+                None
             }
         }
-        self.length.fetch_add(1, SeqCst);
-        old
+    }
+
+    // The flag is true only for the one caller whose f() ended up in the table. f() runs under
+    // the bucket lock, and a dropped weak value is replaced like in or_insert.
+    pub fn get_or_insert_with_status<F: FnOnce() -> V>(&self, key: K, f: F) -> (Arc<V>, bool) {
+        let mut entry = self.entry(key);
+        if let Some(value) = entry.stored().and_then(|stored| stored.value.upgrade()) {
+            return (value, false);
+        }
+        (entry.or_insert(Arc::new(f())), true)
+    }
+
+    pub fn entry(&self, key: K) -> OccupiedOrVacant<'_, K, V> {
+        self.grow_if_needed();
+        let buckets = self.buckets.read();
+        let index = Self::calculate_hash(&key) as usize % buckets.len();
+        let bucket = buckets[index].write_arc();
+        let depth = iter::successors(bucket.as_ref(), |entry| entry.next.as_deref())
+            .position(|entry| entry.key == key);
+        OccupiedOrVacant {
+            bucket,
+            map: self,
+            key,
+            depth,
+        }
+    }
+
+    fn grow_if_needed(&self) {
+        if self.buckets.read().is_empty() {
+            let mut buckets = self.buckets.write();
+            if buckets.is_empty() {
+                *buckets = (0..64).map(|_| Arc::default()).collect();
+                self.bucket_count.store(buckets.len(), SeqCst);
+            }
+        }
+        if self.fill_factor() >= self.load_factor {
+            self.rehash();
+        }
+    }
+
+    // Grows in a single step under the outer write lock
+    pub fn reserve(&self, additional: usize) {
+        let buckets = &mut *self.buckets.write();
+        let entries = self
+            .len()
+            .checked_add(additional)
+            .expect("capacity overflow");
+        let bucket_count = required_buckets(entries, self.load_factor);
+        if bucket_count > buckets.len() {
+            self.resize(buckets, bucket_count);
+        }
     }
 
     fn rehash(&self) {
@@ -102,24 +324,46 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
         if (self.length.load(SeqCst) as f64 / buckets.len() as f64) < self.load_factor {
             return;
         }
-        let new_table: HashMap<K, V> =
-            HashMap::with_exact_capacity(buckets.len() * 2, self.load_factor);
+        self.resize(buckets, buckets.len() * 2);
+    }
+
+    fn resize(&self, buckets: &mut Vec<Bucket<K, V>>, bucket_count: usize) {
+        let new_table: HashMap<K, V> = HashMap::with_exact_capacity(bucket_count, self.load_factor);
         for bucket in buckets.iter() {
             let bucket = &mut *bucket.write();
             if let Some(entry) = bucket.take() {
-                new_table.insert(entry.key, entry.value);
+                new_table.insert_value(entry.key, entry.value);
                 let mut current = entry.next;
                 while let Some(entry) = current {
-                    new_table.insert(entry.key, entry.value);
+                    new_table.insert_value(entry.key, entry.value);
                     current = entry.next;
                 }
             }
         }
         let new_buckets = &mut *new_table.buckets.write();
         mem::swap(buckets, new_buckets);
+        self.bucket_count.store(buckets.len(), SeqCst);
     }
 
     pub fn get(&self, key: &K) -> Option<Arc<V>> {
+        self.find(key, Value::upgrade)
+    }
+
+    // Clones the value itself under the bucket lock instead of handing out the Arc
+    pub fn get_or_clone(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.find(key, Value::cloned)
+    }
+
+    // Membership check without cloning or upgrading the value's Arc
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.find(key, |value| value.is_live().then_some(()))
+            .is_some()
+    }
+
+    fn find<R>(&self, key: &K, read: impl FnOnce(&Value<V>) -> Option<R>) -> Option<R> {
         let buckets = &*self.buckets.read();
         if buckets.is_empty() {
             return None;
@@ -131,7 +375,7 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
             Some(bucket) => {
                 // First bucket is a hit
                 if bucket.key == *key {
-                    return Some(bucket.value.clone());
+                    return read(&bucket.value);
                 }
                 // First bucket is a miss and has next
                 let mut current = &bucket.next;
@@ -139,7 +383,7 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
                     match current {
                         // Entry located
                         Some(entry) if entry.key == *key => {
-                            return Some(entry.value.clone());
+                            return read(&entry.value);
                         }
                         // Cycle through the linked list
                         Some(entry) => {
@@ -156,15 +400,59 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
         result
     }
 
+    pub fn get_weak(&self, key: &K) -> Option<Arc<V>> {
+        let value = self.get(key);
+        if value.is_none() {
+            // Only prune under the bucket lock, a live value may have replaced the dead one
+            let pruned =
+                HashMap::pre_locked_remove_if(&self.buckets.read(), &self.length, key, |value| {
+                    value.upgrade().is_none()
+                });
+            if pruned.is_some() {
+                self.shrink_if_sparse();
+            }
+        }
+        value
+    }
+
     pub fn remove(&self, key: &K) -> Option<Arc<V>> {
-        let buckets = self.buckets.read();
-        HashMap::pre_locked_remove(&*buckets, &self.length, key)
+        let removed =
+            HashMap::pre_locked_remove_if(&self.buckets.read(), &self.length, key, |_| true);
+        if removed.is_some() {
+            self.shrink_if_sparse();
+        }
+        removed.and_then(Value::into_strong)
+    }
+
+    // Off by default. Once removals drop the fill factor below a quarter of the load factor, the
+    // buckets are shrunk to half the load factor, leaving room both ways before the next resize.
+    // Checked after remove, entry().remove() and dead weak entries pruned by get_weak.
+    pub fn set_auto_shrink(&mut self, enabled: bool) {
+        self.auto_shrink = enabled;
     }
-    fn pre_locked_remove(
-        buckets: &Vec<RwLock<Option<Entry<K, V>>>>,
+
+    fn shrink_if_sparse(&self) {
+        if !self.auto_shrink || self.approx_fill_factor() >= self.load_factor / 4.0 {
+            return;
+        }
+        let buckets = &mut *self.buckets.write();
+        let length = self.length.load(SeqCst);
+        // The lock free estimate may be stale, or another remover shrank the buckets meanwhile
+        if (length as f64 / buckets.len() as f64) >= self.load_factor / 4.0 {
+            return;
+        }
+        let bucket_count = required_buckets(length, self.load_factor / 2.0).max(64);
+        if bucket_count < buckets.len() {
+            self.resize(buckets, bucket_count);
+        }
+    }
+
+    fn pre_locked_remove_if(
+        buckets: &[Bucket<K, V>],
         length: &AtomicUsize,
         key: &K,
-    ) -> Option<Arc<V>> {
+        condition: impl Fn(&Value<V>) -> bool,
+    ) -> Option<Value<V>> {
         if buckets.is_empty() {
             return None;
         }
@@ -176,25 +464,28 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
             Some(bucket) => {
                 match &mut bucket.next {
                     // First bucket is a hit and has no next
-                    None if bucket.key == *key => {
+                    None if bucket.key == *key && condition(&bucket.value) => {
                         let result = entry.take().unwrap();
                         length.fetch_sub(1, SeqCst);
                         Some(result.value)
                     }
                     // Fist bucket is a hit and has next
-                    Some(_next) if bucket.key == *key => {
+                    Some(_next) if bucket.key == *key && condition(&bucket.value) => {
                         let result = entry.take().unwrap();
                         *entry = Some(*result.next.unwrap());
                         length.fetch_sub(1, SeqCst);
                         Some(result.value)
                     }
                     // First bucket is a miss and has next
-                    Some(miss) => {
-                        let mut current = &mut miss.next;
+                    Some(_) => {
+                        let mut current = &mut bucket.next;
                         loop {
                             match current {
                                 // Entry located
                                 Some(entry) if entry.key == *key => {
+                                    if !condition(&entry.value) {
+                                        return None;
+                                    }
                                     let mut result = current.take().unwrap();
                                     *current = result.next.take();
                                     length.fetch_sub(1, SeqCst);
@@ -219,6 +510,127 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
     }
 }
 
+#[cfg(feature = "rayon")]
+impl<K: Hash + Eq + Send + Sync, V: Send + Sync> HashMap<K, V> {
+    // Reserves once up front, so none of the parallel inserts has to rehash
+    pub fn par_extend<I: IntoParallelIterator<Item = (K, Arc<V>)>>(&self, iter: I) {
+        let iter = iter.into_par_iter();
+        self.reserve(iter.opt_len().unwrap_or(0));
+        iter.for_each(|(key, value)| {
+            self.insert(key, value);
+        });
+    }
+}
+
+impl<Q: ?Sized + Hash + Eq, V> HashMap<Arc<Q>, V> {
+    // Keeps the allocation of an already stored equal key and returns it, so callers can
+    // drop their own copy and share the stored one
+    pub fn insert_interned_key(&self, key: Arc<Q>, value: Arc<V>) -> Arc<Q> {
+        let mut interned = key.clone();
+        self.insert_value_with(key, Value::Strong(value), |stored, _| {
+            interned = stored.clone()
+        });
+        interned
+    }
+}
+
+impl<'a, K: Hash + Eq, V> OccupiedOrVacant<'a, K, V> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    // An entry whose weak value was dropped counts as vacant and is reused
+    pub fn or_insert(mut self, default: Arc<V>) -> Arc<V> {
+        if let Some(entry) = self.stored() {
+            if let Some(value) = entry.value.upgrade() {
+                return value;
+            }
+            entry.value = Value::Strong(default.clone());
+            return default;
+        }
+        let chain = &mut *self.bucket;
+        let next = chain.take().map(Box::new);
+        *chain = Some(Entry {
+            key: self.key,
+            value: Value::Strong(default.clone()),
+            next,
+        });
+        self.map.length.fetch_add(1, SeqCst);
+        default
+    }
+
+    // Modifies through Arc::make_mut, so readers holding the old Arc keep seeing the old value.
+    // A live weak value is copied and stored as strong from then on.
+    pub fn and_modify<F: FnOnce(&mut V)>(mut self, f: F) -> Self
+    where
+        V: Clone,
+    {
+        if let Some(entry) = self.stored() {
+            match &mut entry.value {
+                Value::Strong(value) => f(Arc::make_mut(value)),
+                Value::Weak(value) => {
+                    if let Some(mut value) = value.upgrade() {
+                        f(Arc::make_mut(&mut value));
+                        entry.value = Value::Strong(value);
+                    }
+                }
+            }
+        }
+        self
+    }
+
+    pub fn remove(mut self) -> Option<Arc<V>> {
+        let depth = self.depth?;
+        let chain = &mut *self.bucket;
+        let removed = if depth == 0 {
+            let mut head = chain.take().unwrap();
+            *chain = head.next.take().map(|next| *next);
+            head
+        } else {
+            let mut previous = chain.as_mut().unwrap();
+            for _ in 1..depth {
+                previous = previous.next.as_deref_mut().unwrap();
+            }
+            let mut removed = previous.next.take().unwrap();
+            previous.next = removed.next.take();
+            *removed
+        };
+        self.map.length.fetch_sub(1, SeqCst);
+        // The bucket lock and the table's read lock have to go before a shrink can resize
+        let map = self.map;
+        drop(self);
+        map.shrink_if_sparse();
+        removed.value.into_strong()
+    }
+
+    fn stored(&mut self) -> Option<&mut Entry<K, V>> {
+        let depth = self.depth?;
+        let mut entry = self.bucket.as_mut().unwrap();
+        for _ in 0..depth {
+            entry = entry.next.as_deref_mut().unwrap();
+        }
+        Some(entry)
+    }
+}
+
+impl<K: Hash + Eq + Clone, V> Iterator for IterLocked<'_, K, V> {
+    type Item = (K, Arc<V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.chain.next() {
+                return Some(item);
+            }
+            let bucket = self.buckets.get(self.index)?.read();
+            self.index += 1;
+            self.chain = iter::successors(bucket.as_ref(), |entry| entry.next.as_deref())
+                .filter_map(|entry| Some((entry.key.clone(), entry.value.upgrade()?)))
+                .collect::<Vec<_>>()
+                .into_iter();
+        }
+    }
+}
+
 impl<K: Hash + Eq, V> Default for HashMap<K, V> {
     fn default() -> Self {
         Self::new()
@@ -452,4 +864,564 @@ mod tests {
             assert_eq!(table.get(&i), Some(Arc::new(i)));
         }
     }
+
+    #[test]
+    fn test_approx_fill_factor() {
+        let table = Arc::new(HashMap::new());
+        assert_eq!(table.approx_fill_factor(), 0.0);
+        let mut threads = Vec::new();
+        for t in 0..8 {
+            let table = table.clone();
+            threads.push(thread::spawn(move || {
+                for i in 0..1000 {
+                    table.insert(t * 1000 + i, Arc::new(i));
+                    assert!(table.approx_fill_factor() <= 2.0 * 0.4);
+                }
+            }));
+        }
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(table.approx_fill_factor(), table.fill_factor());
+        table.clear();
+        assert_eq!(table.approx_fill_factor(), 0.0);
+    }
+
+    #[test]
+    fn test_load_factor() {
+        let table: HashMap<i32, i32> = HashMap::with_load_factor(0.7);
+        assert_eq!(table.load_factor(), 0.7);
+        assert_eq!(table.fill_factor(), 0.0);
+    }
+
+    #[test]
+    fn test_remove_second_in_chain() {
+        let table = HashMap::with_exact_capacity(1, 8.0);
+        table.insert(1, Arc::new("one"));
+        table.insert(2, Arc::new("two"));
+        table.insert(3, Arc::new("three"));
+        assert_eq!(table.remove(&2), Some(Arc::new("two")));
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get(&2), None);
+        assert_eq!(table.remove(&3), Some(Arc::new("three")));
+        assert_eq!(table.remove(&1), Some(Arc::new("one")));
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn test_weak_values() {
+        let table = HashMap::new();
+        let value = Arc::new("shared".to_string());
+        table.insert_weak(1, &value);
+        table.insert(2, Arc::new("owned".to_string()));
+        assert_eq!(Arc::strong_count(&value), 1);
+        assert_eq!(table.get_weak(&1), Some(value.clone()));
+        assert_eq!(table.len(), 2);
+        drop(value);
+        assert_eq!(table.get(&1), None);
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get_weak(&1), None);
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.get_weak(&2), Some(Arc::new("owned".to_string())));
+    }
+
+    #[test]
+    fn test_len_counts_dead_weak_entries() {
+        let table = HashMap::new();
+        let value = Arc::new(1);
+        table.insert_weak(1, &value);
+        drop(value);
+        assert_eq!(table.get(&1), None);
+        assert!(!table.contains_key(&1));
+        assert!(table.snapshot_keys().is_empty());
+        assert_eq!(table.len(), 1);
+        assert!(!table.is_empty());
+        assert_eq!(table.remove(&1), None);
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn test_insert_interned() {
+        let table = HashMap::new();
+        let first = table.insert_interned(1, Arc::new(vec![0_u8; 1024]));
+        let second = table.insert_interned(2, Arc::new(vec![0_u8; 1024]));
+        let other = table.insert_interned(3, Arc::new(vec![1_u8; 1024]));
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(!Arc::ptr_eq(&first, &other));
+        assert!(Arc::ptr_eq(
+            &table.get(&1).unwrap(),
+            &table.get(&2).unwrap()
+        ));
+        assert_eq!(Arc::strong_count(&first), 4);
+        assert_eq!(table.len(), 3);
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    struct CaseInsensitive(&'static str);
+
+    impl PartialEq for CaseInsensitive {
+        fn eq(&self, other: &Self) -> bool {
+            self.0.eq_ignore_ascii_case(other.0)
+        }
+    }
+
+    impl Eq for CaseInsensitive {}
+
+    impl Hash for CaseInsensitive {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.0.to_ascii_lowercase().hash(state);
+        }
+    }
+
+    #[test]
+    fn test_overwrite_keeps_new_key() {
+        let table = HashMap::new();
+        assert_eq!(table.insert(CaseInsensitive("Foo"), Arc::new(1)), None);
+        assert_eq!(
+            table.insert(CaseInsensitive("foo"), Arc::new(2)),
+            Some(Arc::new(1))
+        );
+        let keys: Vec<_> = table
+            .buckets
+            .read()
+            .iter()
+            .filter_map(|bucket| bucket.read().as_ref().map(|entry| entry.key.0))
+            .collect();
+        assert_eq!(keys, vec!["foo"]);
+    }
+
+    #[test]
+    fn test_clear_keeps_buckets() {
+        let table = HashMap::new();
+        for i in 0..1000 {
+            table.insert(i, Arc::new(i));
+        }
+        let bucket_count = table.buckets.read().len();
+        table.clear();
+        assert!(table.is_empty());
+        assert_eq!(table.buckets.read().len(), bucket_count);
+        assert_eq!(table.get(&1), None);
+        table.insert(1, Arc::new(10));
+        assert_eq!(table.get(&1), Some(Arc::new(10)));
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn test_get_or_clone() {
+        let table = HashMap::new();
+        for i in 0..1000_u64 {
+            table.insert(i, Arc::new(i * 2));
+        }
+        let shared = Arc::new(7_u64);
+        table.insert_weak(1000, &shared);
+        for i in 0..1000 {
+            assert_eq!(table.get_or_clone(&i), Some(i * 2));
+        }
+        assert_eq!(table.get_or_clone(&1000), Some(7));
+        drop(shared);
+        assert_eq!(table.get_or_clone(&1000), None);
+        assert_eq!(table.get_or_clone(&1001), None);
+    }
+
+    #[test]
+    fn test_iter_locked() {
+        let table = HashMap::with_capacity(64);
+        for i in 0..10_000 {
+            table.insert(i, Arc::new(i * 2));
+        }
+        let shared = Arc::new(-1);
+        table.insert_weak(-1, &shared);
+        table.insert_weak(-2, &Arc::new(-2));
+        let mut seen = vec![0; 10_000];
+        let mut weak = 0;
+        for (key, value) in table.iter_locked() {
+            if key == -1 {
+                assert!(Arc::ptr_eq(&value, &shared));
+                weak += 1;
+            } else {
+                assert_eq!(*value, key * 2);
+                seen[key as usize] += 1;
+            }
+        }
+        assert_eq!(weak, 1);
+        assert!(seen.iter().all(|&count| count == 1));
+        // Bucket locks are released between items, so other threads can use the table meanwhile
+        let mut iter = table.iter_locked();
+        let (key, _) = iter.next().unwrap();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                assert!(table.get(&key).is_some());
+                assert!(table.remove(&key).is_some());
+            });
+        });
+        drop(iter);
+        assert_eq!(table.iter_locked().count(), 10_000);
+    }
+
+    #[test]
+    fn test_snapshot_keys() {
+        let table = HashMap::with_exact_capacity(16, 8.0);
+        for i in 0..100 {
+            table.insert(i, Arc::new(i));
+        }
+        table.remove(&50);
+        let shared = Arc::new(1000);
+        table.insert_weak(1000, &shared);
+        table.insert_weak(1001, &Arc::new(1001));
+        let mut keys = table.snapshot_keys();
+        keys.sort();
+        let expected: Vec<_> = (0..100).filter(|&i| i != 50).chain([1000]).collect();
+        assert_eq!(keys, expected);
+    }
+
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<HashMap<i32, i32>>();
+        assert_send_sync::<HashMap<String, Vec<u8>>>();
+        assert_send_sync::<HashMap<Arc<str>, std::sync::Mutex<i32>>>();
+
+        // With a second impl applying, the item lookups below turn ambiguous and fail to compile
+        trait AmbiguousIfSend<A> {
+            fn some_item() {}
+        }
+        impl<T: ?Sized> AmbiguousIfSend<()> for T {}
+        impl<T: ?Sized + Send> AmbiguousIfSend<u8> for T {}
+        trait AmbiguousIfSync<A> {
+            fn some_item() {}
+        }
+        impl<T: ?Sized> AmbiguousIfSync<()> for T {}
+        impl<T: ?Sized + Sync> AmbiguousIfSync<u8> for T {}
+        <HashMap<i32, std::rc::Rc<i32>> as AmbiguousIfSend<_>>::some_item();
+        <HashMap<i32, std::rc::Rc<i32>> as AmbiguousIfSync<_>>::some_item();
+    }
+
+    #[test]
+    fn test_capacity() {
+        assert_eq!(HashMap::<i32, i32>::new().capacity(), 0);
+        for n in [1, 10, 1000, 12345] {
+            let table = HashMap::with_capacity(n);
+            assert!(table.capacity() >= n);
+            let bucket_count = table.bucket_count.load(SeqCst);
+            for i in 0..n {
+                table.insert(i, Arc::new(i));
+            }
+            assert_eq!(table.bucket_count.load(SeqCst), bucket_count);
+        }
+    }
+
+    #[test]
+    fn test_multithreaded_reserve() {
+        let table = Arc::new(HashMap::new());
+        table.insert(usize::MAX, Arc::new(0));
+        table.reserve(100_000);
+        assert!(table.capacity() >= 100_001);
+        let bucket_count = table.bucket_count.load(SeqCst);
+        let mut threads = Vec::new();
+        for t in 0..8 {
+            let table = table.clone();
+            threads.push(thread::spawn(move || {
+                for i in 0..12_500 {
+                    table.insert(t * 12_500 + i, Arc::new(i));
+                }
+            }));
+        }
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(table.bucket_count.load(SeqCst), bucket_count);
+        assert_eq!(table.len(), 100_001);
+        for i in 0..100_000 {
+            assert_eq!(table.get(&i), Some(Arc::new(i % 12_500)));
+        }
+    }
+
+    #[test]
+    fn test_concurrent_overwrites() {
+        let table = Arc::new(HashMap::with_capacity(1000));
+        for i in 0..1000 {
+            table.insert(i, Arc::new(i));
+        }
+        let mut threads = Vec::new();
+        for t in 0..4 {
+            let table = table.clone();
+            threads.push(thread::spawn(move || {
+                for round in 1..=20 {
+                    for i in 0..1000 {
+                        let old = table.insert(i, Arc::new(i + (round * 4 + t) * 1000));
+                        assert_eq!(*old.unwrap() % 1000, i);
+                    }
+                }
+            }));
+        }
+        for _ in 0..4 {
+            let table = table.clone();
+            threads.push(thread::spawn(move || {
+                for _ in 0..20 {
+                    for i in 0..1000 {
+                        assert_eq!(*table.get(&i).unwrap() % 1000, i);
+                    }
+                    assert_eq!(table.len(), 1000);
+                }
+            }));
+        }
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(table.len(), 1000);
+        assert_eq!(table.snapshot_keys().len(), 1000);
+    }
+
+    #[test]
+    fn test_insert_interned_key() {
+        let table: HashMap<Arc<str>, i32> = HashMap::new();
+        let first: Arc<str> = Arc::from("key");
+        let second: Arc<str> = Arc::from(String::from("key"));
+        assert!(!Arc::ptr_eq(&first, &second));
+        assert!(Arc::ptr_eq(
+            &table.insert_interned_key(first.clone(), Arc::new(1)),
+            &first
+        ));
+        let interned = table.insert_interned_key(second, Arc::new(2));
+        assert!(Arc::ptr_eq(&interned, &first));
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.get(&first), Some(Arc::new(2)));
+        let stored = table.snapshot_keys();
+        assert!(Arc::ptr_eq(&stored[0], &first));
+
+        let bytes: HashMap<Arc<[u8]>, i32> = HashMap::new();
+        let first: Arc<[u8]> = Arc::from(&b"key"[..]);
+        bytes.insert_interned_key(first.clone(), Arc::new(1));
+        let interned = bytes.insert_interned_key(Arc::from(&b"key"[..]), Arc::new(2));
+        assert!(Arc::ptr_eq(&interned, &first));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_extend() {
+        let table = HashMap::new();
+        table.insert(-1, Arc::new(-1));
+        table.par_extend((0..100_000).into_par_iter().map(|i| (i, Arc::new(i * 2))));
+        let bucket_count = table.bucket_count.load(SeqCst);
+        assert_eq!(bucket_count, required_buckets(100_001, 0.4));
+        assert_eq!(table.len(), 100_001);
+        assert_eq!(table.get(&-1), Some(Arc::new(-1)));
+        for i in 0..100_000 {
+            assert_eq!(table.get(&i), Some(Arc::new(i * 2)));
+        }
+    }
+
+    #[test]
+    fn test_contains_key_multithreaded() {
+        let table = Arc::new(HashMap::with_capacity(64));
+        for i in 0..1000 {
+            table.insert(i, Arc::new(i));
+        }
+        let mut threads = Vec::new();
+        for _ in 0..8 {
+            let table = table.clone();
+            threads.push(thread::spawn(move || {
+                for i in 0..2000 {
+                    assert_eq!(table.contains_key(&i), i < 1000);
+                }
+            }));
+        }
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_entry_transitions() {
+        let table = HashMap::new();
+        assert_eq!(*table.entry(1).or_insert(Arc::new(10)), 10);
+        assert_eq!(table.len(), 1);
+        assert_eq!(*table.entry(1).or_insert(Arc::new(20)), 10);
+
+        let before = table.get(&1).unwrap();
+        table.entry(1).and_modify(|value| *value += 5);
+        assert_eq!(table.get(&1), Some(Arc::new(15)));
+        assert_eq!(*before, 10);
+        assert_eq!(
+            *table
+                .entry(2)
+                .and_modify(|value| *value += 5)
+                .or_insert(Arc::new(0)),
+            0
+        );
+        assert_eq!(table.len(), 2);
+
+        assert_eq!(table.entry(1).remove(), Some(Arc::new(15)));
+        assert_eq!(table.entry(1).remove(), None);
+        assert_eq!(table.get(&1), None);
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn test_entry_in_chain() {
+        let table = HashMap::with_exact_capacity(1, 100.0);
+        for i in 0..5 {
+            table.insert(i, Arc::new(i));
+        }
+        table.entry(2).and_modify(|value| *value *= 10);
+        assert_eq!(table.entry(3).remove(), Some(Arc::new(3)));
+        assert_eq!(table.entry(0).remove(), Some(Arc::new(0)));
+        assert_eq!(table.entry(4).remove(), Some(Arc::new(4)));
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get(&1), Some(Arc::new(1)));
+        assert_eq!(table.get(&2), Some(Arc::new(20)));
+    }
+
+    #[test]
+    fn test_entry_dead_weak_is_vacant() {
+        let table = HashMap::new();
+        let value = Arc::new(1);
+        table.insert_weak(1, &value);
+        drop(value);
+        assert_eq!(*table.entry(1).or_insert(Arc::new(2)), 2);
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.get(&1), Some(Arc::new(2)));
+    }
+
+    #[test]
+    fn test_entry_concurrent_increments() {
+        let table = Arc::new(HashMap::new());
+        let mut threads = Vec::new();
+        for _ in 0..8 {
+            let table = table.clone();
+            threads.push(thread::spawn(move || {
+                for i in 0..1000 {
+                    table
+                        .entry(i % 10)
+                        .and_modify(|value| *value += 1)
+                        .or_insert(Arc::new(1));
+                }
+            }));
+        }
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(table.len(), 10);
+        for i in 0..10 {
+            assert_eq!(table.get(&i), Some(Arc::new(800)));
+        }
+        assert_eq!(table.entry(0).remove(), Some(Arc::new(800)));
+        assert_eq!(table.len(), 9);
+    }
+
+    #[test]
+    fn test_get_or_insert_with_status_single_winner() {
+        use std::sync::Barrier;
+
+        let table = Arc::new(HashMap::with_capacity(16));
+        let barrier = Arc::new(Barrier::new(16));
+        let mut threads = Vec::new();
+        for i in 0..16 {
+            let table = table.clone();
+            let barrier = barrier.clone();
+            threads.push(thread::spawn(move || {
+                barrier.wait();
+                table.get_or_insert_with_status("shared", || i)
+            }));
+        }
+        let results: Vec<_> = threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect();
+        let winners: Vec<_> = results.iter().filter(|(_, inserted)| *inserted).collect();
+        assert_eq!(winners.len(), 1);
+        for (value, _) in &results {
+            assert!(Arc::ptr_eq(value, &winners[0].0));
+        }
+        let (value, inserted) = table.get_or_insert_with_status("shared", || 100);
+        assert!(!inserted);
+        assert!(Arc::ptr_eq(&value, &winners[0].0));
+        assert_eq!(table.len(), 1);
+
+        let dropped = Arc::new(7);
+        table.insert_weak("weak", &dropped);
+        drop(dropped);
+        let (value, inserted) = table.get_or_insert_with_status("weak", || 8);
+        assert!(inserted);
+        assert_eq!(*value, 8);
+        assert_eq!(table.len(), 2);
+    }
+
+    #[test]
+    fn test_auto_shrink() {
+        let mut table = HashMap::new();
+        table.set_auto_shrink(true);
+        let table = Arc::new(table);
+        let mut threads = Vec::new();
+        for t in 0..4 {
+            let table = table.clone();
+            threads.push(thread::spawn(move || {
+                for i in (t..10_000).step_by(4) {
+                    table.insert(i, Arc::new(i));
+                }
+            }));
+        }
+        for thread in threads.drain(..) {
+            thread.join().unwrap();
+        }
+        let grown = table.bucket_count.load(SeqCst);
+        for t in 0..4 {
+            let table = table.clone();
+            threads.push(thread::spawn(move || {
+                for i in (100 + t..10_000).step_by(4) {
+                    assert_eq!(table.remove(&i), Some(Arc::new(i)));
+                }
+            }));
+        }
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let shrunk = table.bucket_count.load(SeqCst);
+        assert!(shrunk < grown / 4);
+        assert!(table.fill_factor() < table.load_factor());
+        assert_eq!(table.len(), 100);
+        for i in 0..100 {
+            assert_eq!(table.get(&i), Some(Arc::new(i)));
+        }
+        assert_eq!(table.get(&100), None);
+    }
+
+    #[test]
+    fn test_auto_shrink_on_entry_remove_and_weak_pruning() {
+        let mut table = HashMap::new();
+        table.set_auto_shrink(true);
+        for i in 0..1000 {
+            table.insert(i, Arc::new(i));
+        }
+        let grown = table.bucket_count.load(SeqCst);
+        for i in 0..1000 {
+            assert_eq!(table.entry(i).remove(), Some(Arc::new(i)));
+        }
+        assert_eq!(table.bucket_count.load(SeqCst), 64);
+
+        let values: Vec<_> = (0..1000).map(Arc::new).collect();
+        for (i, value) in values.iter().enumerate() {
+            table.insert_weak(i, value);
+        }
+        assert_eq!(table.bucket_count.load(SeqCst), grown);
+        drop(values);
+        for i in 0..1000 {
+            assert_eq!(table.get_weak(&i), None);
+        }
+        assert_eq!(table.len(), 0);
+        assert_eq!(table.bucket_count.load(SeqCst), 64);
+    }
+
+    #[test]
+    fn test_no_shrink_by_default() {
+        let table = HashMap::new();
+        for i in 0..1000 {
+            table.insert(i, Arc::new(i));
+        }
+        let bucket_count = table.bucket_count.load(SeqCst);
+        for i in 0..1000 {
+            table.remove(&i);
+        }
+        assert_eq!(table.bucket_count.load(SeqCst), bucket_count);
+    }
 }
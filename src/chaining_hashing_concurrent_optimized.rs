@@ -4,8 +4,13 @@ use std::hash::{Hash, Hasher};
 use std::mem;
 use std::sync::Arc;
 
+#[cfg(feature = "rayon")]
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+type Bucket<K, V> = RwLock<Option<Entry<K, V>>>;
+
 pub struct HashMap<K: Hash + Eq, V> {
-    buckets: Vec<RwLock<Option<Entry<K, V>>>>,
+    buckets: RwLock<Vec<Bucket<K, V>>>,
 }
 
 struct Entry<K: Hash + Eq, V> {
@@ -21,32 +26,144 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
     }
     fn with_exact_capacity(capacity: usize) -> Self {
         Self {
-            buckets: (0..capacity).map(|_| RwLock::new(None)).collect(),
+            buckets: RwLock::new((0..capacity).map(|_| RwLock::new(None)).collect()),
         }
     }
     pub fn clear(&self) {
-        for element in self.buckets.iter() {
+        for element in self.buckets.read().iter() {
             *element.write() = None;
         }
     }
 
+    pub fn resize(&self, new_capacity: usize) {
+        assert!(new_capacity > 0);
+        let bucket_count = new_capacity.checked_mul(8).expect("capacity overflow");
+        Self::rehash_into(&mut self.buckets.write(), bucket_count);
+    }
+
+    pub fn reserve(&self, additional: usize) {
+        let buckets = &mut *self.buckets.write();
+        // The length is not tracked to keep inserts free of a shared counter, so count here
+        let length: usize = buckets
+            .iter_mut()
+            .map(|bucket| {
+                let mut count = 0;
+                let mut current = bucket.get_mut().as_ref();
+                while let Some(entry) = current {
+                    count += 1;
+                    current = entry.next.as_deref();
+                }
+                count
+            })
+            .sum();
+        let bucket_count = length
+            .checked_add(additional)
+            .and_then(|required| required.checked_mul(8))
+            .expect("capacity overflow");
+        if bucket_count > buckets.len() {
+            Self::rehash_into(buckets, bucket_count);
+        }
+    }
+
+    fn rehash_into(buckets: &mut Vec<Bucket<K, V>>, bucket_count: usize) {
+        let old_buckets = mem::replace(
+            buckets,
+            (0..bucket_count).map(|_| RwLock::new(None)).collect(),
+        );
+        for bucket in old_buckets {
+            let mut current = bucket.into_inner();
+            while let Some(mut entry) = current {
+                current = entry.next.take().map(|next| *next);
+                Self::push_front(buckets, entry);
+            }
+        }
+    }
+
     fn calculate_hash(key: &K) -> u64 {
         let mut s = DefaultHasher::new();
         key.hash(&mut s);
         s.finish()
     }
 
+    // Like get_or_insert_with, one bucket write lock covers the lookup and the insert, so no
+    // other caller can add the key in between
     pub fn insert(&self, key: K, value: Arc<V>) -> Option<Arc<V>> {
-        let old = self.remove(&key);
-        let hash = Self::calculate_hash(&key);
-        let index = hash as usize % self.buckets.len();
-        let entry = Entry {
+        let buckets = self.buckets.read();
+        let index = Self::calculate_hash(&key) as usize % buckets.len();
+        let bucket = &mut *buckets[index].write();
+        let mut current = bucket.as_mut();
+        while let Some(entry) = current {
+            if entry.key == key {
+                entry.key = key;
+                return Some(mem::replace(&mut entry.value, value));
+            }
+            current = entry.next.as_deref_mut();
+        }
+        let next = bucket.take().map(Box::new);
+        *bucket = Some(Entry { key, value, next });
+        None
+    }
+
+    // The lookup and the insert share one bucket write lock, so racing callers all get the
+    // Arc of whichever of them got there first
+    pub fn get_or_insert_with<F: FnOnce() -> V>(&self, key: K, f: F) -> Arc<V> {
+        self.get_or_insert_with_status(key, f).0
+    }
+
+    // The flag is true only for the one caller whose f() ended up in the table
+    pub fn get_or_insert_with_status<F: FnOnce() -> V>(&self, key: K, f: F) -> (Arc<V>, bool) {
+        let buckets = self.buckets.read();
+        let index = Self::calculate_hash(&key) as usize % buckets.len();
+        let bucket = &mut *buckets[index].write();
+        let mut current = bucket.as_ref();
+        while let Some(entry) = current {
+            if entry.key == key {
+                return (entry.value.clone(), false);
+            }
+            current = entry.next.as_deref();
+        }
+        let value = Arc::new(f());
+        let next = bucket.take().map(Box::new);
+        *bucket = Some(Entry {
             key,
-            value,
-            next: None,
-        };
+            value: value.clone(),
+            next,
+        });
+        (value, true)
+    }
+
+    // Locks one bucket at a time, so the other buckets stay readable and writable meanwhile
+    pub fn retain<F: FnMut(&K, &V) -> bool>(&self, mut f: F) {
+        let buckets = self.buckets.read();
+        for bucket in buckets.iter() {
+            let chain = &mut *bucket.write();
+            while let Some(head) = chain.as_ref() {
+                if f(&head.key, &head.value) {
+                    break;
+                }
+                let head = chain.take().unwrap();
+                *chain = head.next.map(|next| *next);
+            }
+            let Some(head) = chain.as_mut() else {
+                continue;
+            };
+            let mut current = &mut head.next;
+            while let Some(entry) = current.as_ref() {
+                if f(&entry.key, &entry.value) {
+                    current = &mut current.as_mut().unwrap().next;
+                } else {
+                    let removed = current.take().unwrap();
+                    *current = removed.next;
+                }
+            }
+        }
+    }
+
+    fn push_front(buckets: &[Bucket<K, V>], entry: Entry<K, V>) {
+        let hash = Self::calculate_hash(&entry.key);
+        let index = hash as usize % buckets.len();
 
-        let mut bucket = self.buckets[index].write();
+        let mut bucket = buckets[index].write();
         match &mut *bucket {
             Some(first_entry) => {
                 let next = mem::replace(first_entry, entry);
@@ -56,18 +173,27 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
                 *bucket = Some(entry);
             }
         }
-        old
     }
 
     pub fn get(&self, key: &K) -> Option<Arc<V>> {
+        self.find(key, Arc::clone)
+    }
+
+    // Membership check without cloning the value's Arc
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.find(key, |_| ()).is_some()
+    }
+
+    fn find<R>(&self, key: &K, read: impl FnOnce(&Arc<V>) -> R) -> Option<R> {
+        let buckets = self.buckets.read();
         let hash = Self::calculate_hash(key);
-        let index = hash as usize % self.buckets.len();
+        let index = hash as usize % buckets.len();
 
-        let result = match &*self.buckets[index].read() {
+        let result = match &*buckets[index].read() {
             Some(bucket) => {
                 // First bucket is a hit
                 if bucket.key == *key {
-                    return Some(bucket.value.clone());
+                    return Some(read(&bucket.value));
                 }
                 // First bucket is a miss and has next
                 let mut current = &bucket.next;
@@ -75,7 +201,7 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
                     match current {
                         // Entry located
                         Some(entry) if entry.key == *key => {
-                            return Some(entry.value.clone());
+                            return Some(read(&entry.value));
                         }
                         // Cycle through the linked list
                         Some(entry) => {
@@ -93,10 +219,13 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
     }
 
     pub fn remove(&self, key: &K) -> Option<Arc<V>> {
+        Self::pre_locked_remove(&self.buckets.read(), key)
+    }
+    fn pre_locked_remove(buckets: &[Bucket<K, V>], key: &K) -> Option<Arc<V>> {
         let hash = Self::calculate_hash(key);
-        let index = hash as usize % self.buckets.len();
+        let index = hash as usize % buckets.len();
 
-        let entry = &mut *self.buckets[index].write();
+        let entry = &mut *buckets[index].write();
         match entry {
             Some(bucket) => {
                 match &mut bucket.next {
@@ -112,8 +241,8 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
                         Some(result.value)
                     }
                     // First bucket is a miss and has next
-                    Some(miss) => {
-                        let mut current = &mut miss.next;
+                    Some(_) => {
+                        let mut current = &mut bucket.next;
                         loop {
                             match current {
                                 // Entry located
@@ -141,6 +270,25 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
     }
 }
 
+#[cfg(feature = "rayon")]
+impl<K: Hash + Eq + Send, V: Send + Sync> HashMap<K, V> {
+    // Owning the map makes the locks unnecessary, each thread drains the chains of a range of
+    // buckets
+    pub fn into_par_values(self) -> impl ParallelIterator<Item = Arc<V>> {
+        self.buckets
+            .into_inner()
+            .into_par_iter()
+            .flat_map_iter(|bucket| {
+                let mut current = bucket.into_inner();
+                std::iter::from_fn(move || {
+                    let entry = current.take()?;
+                    current = entry.next.map(|next| *next);
+                    Some(entry.value)
+                })
+            })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -337,4 +485,281 @@ mod tests {
             assert_eq!(table.get(&i), Some(Arc::new(i)));
         }
     }
+
+    #[test]
+    fn test_remove_second_in_chain() {
+        let table = HashMap::with_exact_capacity(1);
+        table.insert(1, Arc::new("one"));
+        table.insert(2, Arc::new("two"));
+        table.insert(3, Arc::new("three"));
+        assert_eq!(table.remove(&2), Some(Arc::new("two")));
+        assert_eq!(table.get(&2), None);
+        assert_eq!(table.remove(&3), Some(Arc::new("three")));
+        assert_eq!(table.remove(&1), Some(Arc::new("one")));
+    }
+
+    #[test]
+    fn test_resize() {
+        let table = HashMap::with_capacity(1);
+        for i in 0..100 {
+            table.insert(i, Arc::new(i));
+        }
+        table.resize(200);
+        assert_eq!(table.buckets.read().len(), 1600);
+        for i in 0..100 {
+            assert_eq!(table.get(&i), Some(Arc::new(i)));
+        }
+        table.reserve(10);
+        assert_eq!(table.buckets.read().len(), 1600);
+        table.reserve(200);
+        assert_eq!(table.buckets.read().len(), 2400);
+    }
+
+    #[test]
+    fn multithreaded_reserve() {
+        let table = Arc::new(HashMap::with_capacity(1));
+        table.reserve(1000);
+        let mut threads = Vec::new();
+        for t in 0..4 {
+            let table = table.clone();
+            threads.push(thread::spawn(move || {
+                for i in (t * 1000)..((t + 1) * 1000) {
+                    table.insert(i, Arc::new(i));
+                    if i % 500 == 0 {
+                        table.reserve(1000);
+                    }
+                }
+            }));
+        }
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert!(table.buckets.read().len() > 8);
+        for i in 0..4000 {
+            assert_eq!(table.get(&i), Some(Arc::new(i)));
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    struct CaseInsensitive(&'static str);
+
+    impl PartialEq for CaseInsensitive {
+        fn eq(&self, other: &Self) -> bool {
+            self.0.eq_ignore_ascii_case(other.0)
+        }
+    }
+
+    impl Eq for CaseInsensitive {}
+
+    impl Hash for CaseInsensitive {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.0.to_ascii_lowercase().hash(state);
+        }
+    }
+
+    #[test]
+    fn test_overwrite_keeps_new_key() {
+        let table = HashMap::with_capacity(8);
+        assert_eq!(table.insert(CaseInsensitive("Foo"), Arc::new(1)), None);
+        assert_eq!(
+            table.insert(CaseInsensitive("foo"), Arc::new(2)),
+            Some(Arc::new(1))
+        );
+        let keys: Vec<_> = table
+            .buckets
+            .read()
+            .iter()
+            .filter_map(|bucket| bucket.read().as_ref().map(|entry| entry.key.0))
+            .collect();
+        assert_eq!(keys, vec!["foo"]);
+    }
+
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<HashMap<i32, i32>>();
+        assert_send_sync::<HashMap<String, Vec<u8>>>();
+        assert_send_sync::<HashMap<Arc<str>, std::sync::Mutex<i32>>>();
+
+        // With a second impl applying, the item lookups below turn ambiguous and fail to compile
+        trait AmbiguousIfSend<A> {
+            fn some_item() {}
+        }
+        impl<T: ?Sized> AmbiguousIfSend<()> for T {}
+        impl<T: ?Sized + Send> AmbiguousIfSend<u8> for T {}
+        trait AmbiguousIfSync<A> {
+            fn some_item() {}
+        }
+        impl<T: ?Sized> AmbiguousIfSync<()> for T {}
+        impl<T: ?Sized + Sync> AmbiguousIfSync<u8> for T {}
+        <HashMap<i32, std::rc::Rc<i32>> as AmbiguousIfSend<_>>::some_item();
+        <HashMap<i32, std::rc::Rc<i32>> as AmbiguousIfSync<_>>::some_item();
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_into_par_values() {
+        let table = HashMap::with_capacity(1000);
+        for i in 0..100_000_u64 {
+            table.insert(i, Arc::new(i * 3));
+        }
+        let sequential: u64 = (0..100_000).map(|i| *table.get(&i).unwrap()).sum();
+        let values = table.into_par_values();
+        assert_eq!(values.map(|value| *value).sum::<u64>(), sequential);
+        assert_eq!(sequential, (0..100_000).map(|i| i * 3).sum::<u64>());
+    }
+
+    #[test]
+    fn test_contains_key_multithreaded() {
+        let table = Arc::new(HashMap::with_capacity(64));
+        for i in 0..1000 {
+            table.insert(i, Arc::new(i));
+        }
+        let mut threads = Vec::new();
+        for _ in 0..8 {
+            let table = table.clone();
+            threads.push(thread::spawn(move || {
+                for i in 0..2000 {
+                    assert_eq!(table.contains_key(&i), i < 1000);
+                }
+            }));
+        }
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(Arc::strong_count(&table.get(&0).unwrap()), 2);
+    }
+
+    #[test]
+    fn test_get_or_insert_with_race() {
+        use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+        use std::sync::Barrier;
+
+        let table = Arc::new(HashMap::with_capacity(16));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(8));
+        let mut threads = Vec::new();
+        for i in 0..8 {
+            let table = table.clone();
+            let calls = calls.clone();
+            let barrier = barrier.clone();
+            threads.push(thread::spawn(move || {
+                barrier.wait();
+                table.get_or_insert_with("shared", || {
+                    calls.fetch_add(1, SeqCst);
+                    i
+                })
+            }));
+        }
+        let values: Vec<_> = threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect();
+        assert_eq!(calls.load(SeqCst), 1);
+        for value in &values {
+            assert!(Arc::ptr_eq(value, &values[0]));
+        }
+        assert!(Arc::ptr_eq(&table.get(&"shared").unwrap(), &values[0]));
+        assert!(Arc::ptr_eq(
+            &table.get_or_insert_with("shared", || 100),
+            &values[0]
+        ));
+    }
+
+    #[test]
+    fn test_get_or_insert_with_status_single_winner() {
+        use std::sync::Barrier;
+
+        let table = Arc::new(HashMap::with_capacity(16));
+        let barrier = Arc::new(Barrier::new(16));
+        let mut threads = Vec::new();
+        for i in 0..16 {
+            let table = table.clone();
+            let barrier = barrier.clone();
+            threads.push(thread::spawn(move || {
+                barrier.wait();
+                table.get_or_insert_with_status("shared", || i)
+            }));
+        }
+        let results: Vec<_> = threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect();
+        let winners: Vec<_> = results.iter().filter(|(_, inserted)| *inserted).collect();
+        assert_eq!(winners.len(), 1);
+        for (value, _) in &results {
+            assert!(Arc::ptr_eq(value, &winners[0].0));
+        }
+        let (value, inserted) = table.get_or_insert_with_status("shared", || 100);
+        assert!(!inserted);
+        assert!(Arc::ptr_eq(&value, &winners[0].0));
+    }
+
+    #[test]
+    fn test_insert_racing_get_or_insert_with_keeps_one_entry() {
+        use std::sync::Barrier;
+
+        let table = Arc::new(HashMap::with_exact_capacity(64));
+        let barrier = Arc::new(Barrier::new(8));
+        let mut threads = Vec::new();
+        for i in 0..8 {
+            let table = table.clone();
+            let barrier = barrier.clone();
+            threads.push(thread::spawn(move || {
+                barrier.wait();
+                for key in 0..20_000 {
+                    if i % 2 == 0 {
+                        table.insert(key, Arc::new(i));
+                    } else {
+                        table.get_or_insert_with(key, || i);
+                    }
+                }
+            }));
+        }
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        for key in 0..20_000 {
+            assert!(table.remove(&key).is_some());
+            assert_eq!(table.get(&key), None, "duplicate entry for {key}");
+        }
+    }
+
+    #[test]
+    fn test_retain_chains() {
+        let table = HashMap::with_exact_capacity(1);
+        for i in 0..10 {
+            table.insert(i, Arc::new(i));
+        }
+        table.retain(|key, _| key % 3 == 1);
+        for i in 0..10 {
+            assert_eq!(table.get(&i).is_some(), i % 3 == 1);
+        }
+        table.retain(|_, _| false);
+        assert_eq!(table.get(&1), None);
+    }
+
+    #[test]
+    fn test_retain_while_reading() {
+        let table = Arc::new(HashMap::with_capacity(64));
+        for i in 0..10_000 {
+            table.insert(i, Arc::new(i));
+        }
+        let mut readers = Vec::new();
+        for _ in 0..4 {
+            let table = table.clone();
+            readers.push(thread::spawn(move || {
+                for i in (0..10_000).step_by(2) {
+                    assert_eq!(table.get(&i), Some(Arc::new(i)));
+                }
+            }));
+        }
+        table.retain(|key, value| key % 2 == 0 && value == key);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        for i in 0..10_000 {
+            assert_eq!(table.get(&i).is_some(), i % 2 == 0);
+        }
+    }
 }
@@ -112,8 +112,8 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
                         Some(result.value)
                     }
                     // First bucket is a miss and has next
-                    Some(miss) => {
-                        let mut current = &mut miss.next;
+                    Some(_) => {
+                        let mut current = &mut bucket.next;
                         loop {
                             match current {
                                 // Entry located
@@ -298,6 +298,20 @@ mod tests {
         assert_eq!(table.get(&2), Some(Arc::new(u64::MIN)));
     }
 
+    #[test]
+    fn test_remove_second_in_chain() {
+        let table = HashMap::with_exact_capacity(1);
+        table.insert(1, Arc::new("one"));
+        table.insert(2, Arc::new("two"));
+        table.insert(3, Arc::new("three"));
+        assert_eq!(table.remove(&2), Some(Arc::new("two")));
+        assert_eq!(table.get(&2), None);
+        assert_eq!(table.remove(&2), None);
+        assert_eq!(table.remove(&3), Some(Arc::new("three")));
+        assert_eq!(table.remove(&1), Some(Arc::new("one")));
+        assert_eq!(table.get(&1), None);
+    }
+
     #[test]
     fn test_remove_from_empty_table() {
         let table: HashMap<i32, i32> = HashMap::with_capacity(1);
@@ -337,4 +351,59 @@ mod tests {
             assert_eq!(table.get(&i), Some(Arc::new(i)));
         }
     }
+
+    #[derive(Debug, Clone, Copy)]
+    struct CaseInsensitive(&'static str);
+
+    impl PartialEq for CaseInsensitive {
+        fn eq(&self, other: &Self) -> bool {
+            self.0.eq_ignore_ascii_case(other.0)
+        }
+    }
+
+    impl Eq for CaseInsensitive {}
+
+    impl Hash for CaseInsensitive {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.0.to_ascii_lowercase().hash(state);
+        }
+    }
+
+    #[test]
+    fn test_overwrite_keeps_new_key() {
+        let table = HashMap::with_capacity(8);
+        assert_eq!(table.insert(CaseInsensitive("Foo"), Arc::new(1)), None);
+        assert_eq!(
+            table.insert(CaseInsensitive("foo"), Arc::new(2)),
+            Some(Arc::new(1))
+        );
+        let keys: Vec<_> = table
+            .buckets
+            .iter()
+            .filter_map(|bucket| bucket.read().as_ref().map(|entry| entry.key.0))
+            .collect();
+        assert_eq!(keys, vec!["foo"]);
+    }
+
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<HashMap<i32, i32>>();
+        assert_send_sync::<HashMap<String, Vec<u8>>>();
+        assert_send_sync::<HashMap<Arc<str>, std::sync::Mutex<i32>>>();
+
+        // With a second impl applying, the item lookups below turn ambiguous and fail to compile
+        trait AmbiguousIfSend<A> {
+            fn some_item() {}
+        }
+        impl<T: ?Sized> AmbiguousIfSend<()> for T {}
+        impl<T: ?Sized + Send> AmbiguousIfSend<u8> for T {}
+        trait AmbiguousIfSync<A> {
+            fn some_item() {}
+        }
+        impl<T: ?Sized> AmbiguousIfSync<()> for T {}
+        impl<T: ?Sized + Sync> AmbiguousIfSync<u8> for T {}
+        <HashMap<i32, std::rc::Rc<i32>> as AmbiguousIfSend<_>>::some_item();
+        <HashMap<i32, std::rc::Rc<i32>> as AmbiguousIfSync<_>>::some_item();
+    }
 }
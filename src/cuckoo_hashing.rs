@@ -1,43 +1,176 @@
 use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
-use std::mem;
+use std::fmt::{Debug, Write};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::{mem, slice, vec};
 
 use rand::Rng;
 
-pub struct HashMap<K: Hash + Eq, V> {
+pub struct HashMap<K: Hash + Eq, V, S = SeededState> {
     buckets: Vec<Option<Entry<K, V>>>,
     length: usize,
-    hasher_a: DefaultHasher,
-    hasher_b: DefaultHasher,
+    // Inserts that had to kick at least one entry, since the last clear
+    collision_events: u64,
+    hasher_a: S,
+    hasher_b: S,
+    // Fresh hashers for a kick cycle, None when the caller supplied the hashers
+    reseed: Option<fn() -> S>,
     load_factor: f64,
 }
 
+// Seeds a fresh DefaultHasher from thread_rng, so each table picks its own pair of hash functions
+#[derive(Clone)]
+pub struct SeededState {
+    seed: u64,
+}
+
 struct Entry<K: Hash + Eq, V> {
     key: K,
     value: V,
 }
 
+pub struct Iter<'a, K: Hash + Eq, V> {
+    buckets: slice::Iter<'a, Option<Entry<K, V>>>,
+    remaining: usize,
+}
+
+pub struct IterMut<'a, K: Hash + Eq, V> {
+    buckets: slice::IterMut<'a, Option<Entry<K, V>>>,
+    remaining: usize,
+}
+
+pub struct IntoIter<K: Hash + Eq, V> {
+    buckets: vec::IntoIter<Option<Entry<K, V>>>,
+    remaining: usize,
+}
+
+pub struct HashSet<T: Hash + Eq> {
+    map: HashMap<T, ()>,
+}
+
+#[derive(Debug)]
+pub struct OccupiedError<'a, K, V> {
+    pub key: K,
+    pub value: V,
+    pub current: &'a mut V,
+}
+
+fn scaled_capacity(capacity: usize, factor: usize) -> usize {
+    capacity.checked_mul(factor).expect("capacity overflow")
+}
+
+// Enough buckets to hold entries below the load factor, rounded up to an even count so both
+// halves are the same size
+fn even_bucket_count(entries: usize, load_factor: f64) -> usize {
+    let bucket_count = (entries as f64 / load_factor).ceil();
+    assert!(bucket_count < usize::MAX as f64, "capacity overflow");
+    let bucket_count = bucket_count as usize;
+    bucket_count + bucket_count % 2
+}
+
+impl Default for SeededState {
+    fn default() -> Self {
+        Self {
+            seed: rand::thread_rng().gen::<u64>(),
+        }
+    }
+}
+
+impl BuildHasher for SeededState {
+    type Hasher = DefaultHasher;
+
+    fn build_hasher(&self) -> DefaultHasher {
+        let mut hasher = DefaultHasher::new();
+        hasher.write_u64(self.seed);
+        hasher
+    }
+}
+
+// The kicked bucket now holds the carried entry, so the new entry either moved into it
+// or was the one kicked out of it
+fn track_kick(placed: Option<usize>, kicked: usize) -> Option<usize> {
+    match placed {
+        None => Some(kicked),
+        Some(index) if index == kicked => None,
+        Some(index) => Some(index),
+    }
+}
+
 impl<K: Hash + Eq, V> HashMap<K, V> {
     pub fn new() -> Self {
         Self::with_exact_capacity(0, 0.4)
     }
     pub fn with_capacity(capacity: usize) -> Self {
-        Self::with_exact_capacity(capacity * 8 * 2, 0.4)
+        Self::with_exact_capacity(even_bucket_count(capacity, 0.4), 0.4)
     }
     pub fn with_load_factor(load_factor: f64) -> Self {
         Self::with_exact_capacity(0, load_factor)
     }
     fn with_exact_capacity(capacity: usize, load_factor: f64) -> Self {
-        let mut rng = rand::thread_rng();
-        let mut hasher_a = DefaultHasher::new();
-        let mut hasher_b = DefaultHasher::new();
-        hasher_a.write_u64(rng.gen::<u64>());
-        hasher_b.write_u64(rng.gen::<u64>());
+        Self {
+            reseed: Some(SeededState::default),
+            ..Self::with_exact_capacity_and_hashers(
+                capacity,
+                load_factor,
+                SeededState::default(),
+                SeededState::default(),
+            )
+        }
+    }
+    // Replays a table that never reseeded, i.e. one built by from_seeds or one without a kick
+    // cycle so far: the same inserts give the same placement and iteration order. Random new
+    // seeds would break that, so like with_hashers a kick cycle doubles the table instead.
+    pub fn from_seeds(seed_a: u64, seed_b: u64) -> Self {
+        Self::with_hashers(SeededState { seed: seed_a }, SeededState { seed: seed_b })
+    }
+    pub fn hasher_seeds(&self) -> (u64, u64) {
+        (self.hasher_a.seed, self.hasher_b.seed)
+    }
+    // Refilling after a plain clear reproduces the same placement, kicks included
+    pub fn clear_and_reseed(&mut self) {
+        self.clear();
+        self.hasher_a = SeededState::default();
+        self.hasher_b = SeededState::default();
+    }
+    pub fn shrink_to_fit(&mut self) {
+        self.shrink_to(0);
+    }
+    // Only ever shrinks. A kick cycle at the smaller size is handled like on any insert, so a
+    // from_seeds table keeps its seeds and grows instead of reseeding.
+    pub fn shrink_to(&mut self, min_capacity: usize) {
+        let bucket_count = even_bucket_count(self.length.max(min_capacity), self.load_factor);
+        if bucket_count >= self.buckets.len() {
+            return;
+        }
+        let collision_events = self.collision_events;
+        let entries = mem::replace(&mut self.buckets, (0..bucket_count).map(|_| None).collect());
+        self.length = 0;
+        for entry in entries.into_iter().flatten() {
+            self.insert_entry(entry);
+        }
+        self.collision_events = collision_events;
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> HashMap<K, V, S> {
+    // The two hashers should be independent, with equal ones every key has the same index in
+    // both halves and inserts only succeed by growing the table. The table cannot build new
+    // hashers of its own, so a kick cycle doubles the bucket count instead of reseeding.
+    pub fn with_hashers(hasher_a: S, hasher_b: S) -> Self {
+        Self::with_exact_capacity_and_hashers(0, 0.4, hasher_a, hasher_b)
+    }
+    fn with_exact_capacity_and_hashers(
+        capacity: usize,
+        load_factor: f64,
+        hasher_a: S,
+        hasher_b: S,
+    ) -> Self {
         Self {
             buckets: (0..capacity).map(|_| None).collect(),
             length: 0,
+            collision_events: 0,
             hasher_a,
             hasher_b,
+            reseed: None,
             load_factor,
         }
     }
@@ -47,6 +180,13 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
     pub fn is_empty(&self) -> bool {
         self.length == 0
     }
+    // Entries the table holds before it has to grow
+    pub fn capacity(&self) -> usize {
+        (self.buckets.len() as f64 * self.load_factor) as usize
+    }
+    pub fn load_factor(&self) -> f64 {
+        self.load_factor
+    }
     pub fn fill_factor(&self) -> f64 {
         if self.buckets.is_empty() {
             0.0
@@ -54,52 +194,105 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
             self.length as f64 / self.buckets.len() as f64
         }
     }
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            buckets: self.buckets.iter(),
+            remaining: self.length,
+        }
+    }
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut {
+            buckets: self.buckets.iter_mut(),
+            remaining: self.length,
+        }
+    }
+    pub fn collisions(&self) -> u64 {
+        self.collision_events
+    }
     pub fn clear(&mut self) {
         self.length = 0;
+        self.collision_events = 0;
         for element in self.buckets.iter_mut() {
             *element = None;
         }
     }
+    fn calculate_hash(key: &K, hasher: &S) -> u64 {
+        hasher.hash_one(key)
+    }
+
+    // Index into the A half and into the B half of the buckets
+    pub fn candidate_indices(&self, key: &K) -> (usize, usize) {
+        assert!(!self.buckets.is_empty(), "table has no buckets");
+        let half = self.buckets.len() / 2;
+        (
+            Self::calculate_hash(key, &self.hasher_a) as usize % half,
+            Self::calculate_hash(key, &self.hasher_b) as usize % (self.buckets.len() - half),
+        )
+    }
 
-    fn calculate_hash(key: &K, hasher: &DefaultHasher) -> u64 {
-        let mut hasher = hasher.clone();
-        key.hash(&mut hasher);
-        hasher.finish()
+    // Bucket holding the key, found with a single hash of each candidate
+    fn find_index(&self, key: &K) -> Option<usize> {
+        if self.is_empty() {
+            return None;
+        }
+        let (index_a, index_b) = self.candidate_indices(key);
+        let half = self.buckets.len() / 2;
+        [index_a, half + index_b]
+            .into_iter()
+            .find(|&index| matches!(&self.buckets[index], Some(entry) if entry.key == *key))
     }
 
     pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.insert_entry(Entry { key, value }).0
+    }
+
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<&mut V, OccupiedError<'_, K, V>> {
+        if let Some(index) = self.find_index(&key) {
+            return Err(OccupiedError {
+                current: &mut self.buckets[index].as_mut().unwrap().value,
+                key,
+                value,
+            });
+        }
+        let (_, index) = self.insert_entry(Entry { key, value });
+        Ok(&mut self.buckets[index].as_mut().unwrap().value)
+    }
+
+    // Returns the replaced value and the bucket the key ended up in
+    fn insert_entry(&mut self, entry: Entry<K, V>) -> (Option<V>, usize) {
         if self.buckets.is_empty() {
             self.buckets = (0..64).map(|_| None).collect();
         }
         if self.fill_factor() >= self.load_factor {
             self.rehash(2);
         }
+        let (index_a, index_b) = self.candidate_indices(&entry.key);
         let half = self.buckets.len() / 2;
         let (buckets_a, buckets_b) = self.buckets.split_at_mut(half);
-        let entry = Entry { key, value };
-        let index_a = Self::calculate_hash(&entry.key, &self.hasher_a) as usize % buckets_a.len();
-        let index_b = Self::calculate_hash(&entry.key, &self.hasher_b) as usize % buckets_b.len();
         match (buckets_a.get_mut(index_a), buckets_b.get_mut(index_b)) {
             (Some(bucket_a), Some(bucket_b)) => match (bucket_a, bucket_b) {
                 (Some(entry_a), _) if entry_a.key == entry.key => {
-                    Some(mem::replace(&mut entry_a.value, entry.value))
+                    (Some(mem::replace(entry_a, entry).value), index_a)
                 }
                 (_, Some(entry_b)) if entry_b.key == entry.key => {
-                    Some(mem::replace(&mut entry_b.value, entry.value))
+                    (Some(mem::replace(entry_b, entry).value), half + index_b)
                 }
                 (bucket_a @ None, _) => {
                     *bucket_a = Some(entry);
                     self.length += 1;
-                    None
+                    (None, index_a)
                 }
                 (_, bucket_b @ None) => {
                     *bucket_b = Some(entry);
                     self.length += 1;
-                    None
+                    (None, half + index_b)
                 }
                 // Kick an entry
                 (Some(entry_a), Some(_)) => {
+                    self.collision_events += 1;
                     let mut entry = mem::replace(entry_a, entry);
+                    // Bucket of the new entry, None while it is the one being kicked around
+                    let mut placed = Some(index_a);
                     let mut fill_a = false;
                     for _ in 0..self.length {
                         let index_a = Self::calculate_hash(&entry.key, &self.hasher_a) as usize
@@ -111,19 +304,21 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
                                 (bucket_a @ None, _) => {
                                     *bucket_a = Some(entry);
                                     self.length += 1;
-                                    return None;
+                                    return (None, placed.unwrap_or(index_a));
                                 }
                                 (_, bucket_b @ None) => {
                                     *bucket_b = Some(entry);
                                     self.length += 1;
-                                    return None;
+                                    return (None, placed.unwrap_or(half + index_b));
                                 }
                                 (Some(entry_a), Some(_)) if fill_a => {
                                     entry = mem::replace(entry_a, entry);
+                                    placed = track_kick(placed, index_a);
                                     fill_a = false;
                                 }
                                 (Some(_), Some(entry_b)) => {
                                     entry = mem::replace(entry_b, entry);
+                                    placed = track_kick(placed, half + index_b);
                                     fill_a = true;
                                 }
                             },
@@ -132,8 +327,29 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
                             }
                         }
                     }
-                    self.rehash(1);
-                    self.insert(entry.key, entry.value)
+                    // Keep the new entry out of the rebuild so its bucket is known afterwards
+                    let new_entry = placed.map(|index| self.buckets[index].take().unwrap());
+                    // The reinserts below belong to this insert and must not count again
+                    let collision_events = self.collision_events;
+                    match self.reseed {
+                        Some(reseed) => {
+                            self.hasher_a = reseed();
+                            self.hasher_b = reseed();
+                            self.rehash(1);
+                        }
+                        // The hashers stay the same across a resize, so only more buckets can
+                        // break the cycle
+                        None => self.rehash(2),
+                    }
+                    let result = match new_entry {
+                        Some(new_entry) => {
+                            self.insert_entry(entry);
+                            self.insert_entry(new_entry)
+                        }
+                        None => self.insert_entry(entry),
+                    };
+                    self.collision_events = collision_events;
+                    result
                 }
             },
             _ => {
@@ -142,25 +358,40 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
         }
     }
 
+    pub fn reserve(&mut self, additional: usize) {
+        let entries = self
+            .length
+            .checked_add(additional)
+            .expect("capacity overflow");
+        let bucket_count = even_bucket_count(entries, self.load_factor);
+        if bucket_count > self.buckets.len() {
+            self.resize(bucket_count);
+        }
+    }
+
     fn rehash(&mut self, resize_factor: usize) {
-        let mut new_table =
-            HashMap::with_exact_capacity(self.buckets.len() * resize_factor, self.load_factor);
-        for bucket in self.buckets.iter_mut() {
-            if let Some(Entry { key, value }) = bucket.take() {
-                new_table.insert(key, value);
-            }
+        self.resize(scaled_capacity(self.buckets.len(), resize_factor));
+    }
+
+    fn resize(&mut self, bucket_count: usize) {
+        let old_buckets =
+            mem::replace(&mut self.buckets, (0..bucket_count).map(|_| None).collect());
+        // Moving the entries over is not a collision of any new insert
+        let collision_events = self.collision_events;
+        self.length = 0;
+        for Entry { key, value } in old_buckets.into_iter().flatten() {
+            self.insert(key, value);
         }
-        mem::swap(self, &mut new_table);
+        self.collision_events = collision_events;
     }
 
     pub fn get(&self, key: &K) -> Option<&V> {
         if self.is_empty() {
             return None;
         }
+        let (index_a, index_b) = self.candidate_indices(key);
         let half = self.buckets.len() / 2;
         let (buckets_a, buckets_b) = self.buckets.split_at(half);
-        let index_a = Self::calculate_hash(key, &self.hasher_a) as usize % buckets_a.len();
-        let index_b = Self::calculate_hash(key, &self.hasher_b) as usize % buckets_b.len();
 
         match (buckets_a.get(index_a), buckets_b.get(index_b)) {
             (Some(bucket_a), Some(bucket_b)) => match (bucket_a, bucket_b) {
@@ -174,14 +405,75 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
         }
     }
 
+    // The A half and the B half listed separately, indexed like candidate_indices
+    pub fn debug_layout(&self) -> String
+    where
+        K: Debug,
+    {
+        let (buckets_a, buckets_b) = self.buckets.split_at(self.buckets.len() / 2);
+        let mut layout = String::new();
+        for (name, buckets) in [("A", buckets_a), ("B", buckets_b)] {
+            writeln!(layout, "{name}:").unwrap();
+            for (index, bucket) in buckets.iter().enumerate() {
+                match bucket {
+                    Some(entry) => writeln!(layout, "  {index}: Entry({:?})", entry.key),
+                    None => writeln!(layout, "  {index}: None"),
+                }
+                .unwrap();
+            }
+        }
+        layout
+    }
+
+    // 1 when the key sits in its A bucket, 2 when a lookup also has to read its B bucket
+    pub fn lookup_cost(&self, key: &K) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        let (index_a, index_b) = self.candidate_indices(key);
+        let half = self.buckets.len() / 2;
+        match (&self.buckets[index_a], &self.buckets[half + index_b]) {
+            (Some(entry_a), _) if entry_a.key == *key => Some(1),
+            (_, Some(entry_b)) if entry_b.key == *key => Some(2),
+            (_, _) => None,
+        }
+    }
+
+    pub fn get_many<'a>(&'a self, keys: &[K]) -> Vec<Option<&'a V>> {
+        if self.is_empty() {
+            return keys.iter().map(|_| None).collect();
+        }
+        let half = self.buckets.len() / 2;
+        let (buckets_a, buckets_b) = self.buckets.split_at(half);
+        // Hash the whole batch before touching the buckets
+        let indices: Vec<(usize, usize)> =
+            keys.iter().map(|key| self.candidate_indices(key)).collect();
+        keys.iter()
+            .zip(indices)
+            .map(
+                |(key, (index_a, index_b))| match (&buckets_a[index_a], &buckets_b[index_b]) {
+                    (Some(entry_a), _) if entry_a.key == *key => Some(&entry_a.value),
+                    (_, Some(entry_b)) if entry_b.key == *key => Some(&entry_b.value),
+                    (_, _) => None,
+                },
+            )
+            .collect()
+    }
+
+    pub fn get_copied(&self, key: &K) -> Option<V>
+    where
+        V: Copy,
+    {
+        self.get(key).copied()
+    }
+
     pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
         if self.is_empty() {
             return None;
         }
+        let (index_a, index_b) = self.candidate_indices(key);
         let half = self.buckets.len() / 2;
         let (buckets_a, buckets_b) = self.buckets.split_at_mut(half);
-        let index_a = Self::calculate_hash(key, &self.hasher_a) as usize % buckets_a.len();
-        let index_b = Self::calculate_hash(key, &self.hasher_b) as usize % buckets_b.len();
 
         match (buckets_a.get_mut(index_a), buckets_b.get_mut(index_b)) {
             (Some(bucket_a), Some(bucket_b)) => match (bucket_a, bucket_b) {
@@ -195,14 +487,18 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
         }
     }
 
+    pub fn replace_value(&mut self, key: &K, value: V) -> Option<V> {
+        self.get_mut(key)
+            .map(|current| mem::replace(current, value))
+    }
+
     pub fn remove(&mut self, key: &K) -> Option<V> {
         if self.is_empty() {
             return None;
         }
+        let (index_a, index_b) = self.candidate_indices(key);
         let half = self.buckets.len() / 2;
         let (buckets_a, buckets_b) = self.buckets.split_at_mut(half);
-        let index_a = Self::calculate_hash(key, &self.hasher_a) as usize % buckets_a.len();
-        let index_b = Self::calculate_hash(key, &self.hasher_b) as usize % buckets_b.len();
 
         match (buckets_a.get_mut(index_a), buckets_b.get_mut(index_b)) {
             (Some(bucket_a), Some(bucket_b)) => match (&bucket_a, &bucket_b) {
@@ -221,6 +517,40 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
             }
         }
     }
+
+    // Walks both halves of the bucket vector. Entries are only removed as the iterator reaches them
+    pub fn extract_if<'a, F: FnMut(&K, &mut V) -> bool + 'a>(
+        &'a mut self,
+        mut pred: F,
+    ) -> impl Iterator<Item = (K, V)> + 'a {
+        let length = &mut self.length;
+        self.buckets.iter_mut().filter_map(move |bucket| {
+            let entry = bucket.as_mut()?;
+            if !pred(&entry.key, &mut entry.value) {
+                return None;
+            }
+            *length -= 1;
+            let entry = bucket.take()?;
+            Some((entry.key, entry.value))
+        })
+    }
+
+    #[cfg(any(test, feature = "invariant-checks"))]
+    pub fn verify_invariants(&self) {
+        let half = self.buckets.len() / 2;
+        let mut count = 0;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            if let Some(entry) = bucket {
+                let (index_a, index_b) = self.candidate_indices(&entry.key);
+                assert!(
+                    index == index_a || index == half + index_b,
+                    "entry is outside both of its candidate buckets"
+                );
+                count += 1;
+            }
+        }
+        assert_eq!(count, self.length, "length does not match the entry count");
+    }
 }
 impl<K: Hash + Eq, V> Default for HashMap<K, V> {
     fn default() -> Self {
@@ -228,6 +558,130 @@ impl<K: Hash + Eq, V> Default for HashMap<K, V> {
     }
 }
 
+impl<K: Hash + Eq, V, S: BuildHasher> Extend<(K, V)> for HashMap<K, V, S> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<'a, K: Hash + Eq, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.buckets.by_ref().flatten().next()?;
+        self.remaining -= 1;
+        Some((&entry.key, &entry.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K: Hash + Eq, V> ExactSizeIterator for Iter<'_, K, V> {}
+
+impl<'a, K: Hash + Eq, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.buckets.by_ref().flatten().next()?;
+        self.remaining -= 1;
+        Some((&entry.key, &mut entry.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K: Hash + Eq, V> ExactSizeIterator for IterMut<'_, K, V> {}
+
+impl<K: Hash + Eq, V, S> IntoIterator for HashMap<K, V, S> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            buckets: self.buckets.into_iter(),
+            remaining: self.length,
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.buckets.by_ref().flatten().next()?;
+        self.remaining -= 1;
+        Some((entry.key, entry.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K: Hash + Eq, V> ExactSizeIterator for IntoIter<K, V> {}
+
+impl<T: Hash + Eq> HashSet<T> {
+    pub fn new() -> Self {
+        Self {
+            map: HashMap::new(),
+        }
+    }
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            map: HashMap::with_capacity(capacity),
+        }
+    }
+    pub fn with_load_factor(load_factor: f64) -> Self {
+        Self {
+            map: HashMap::with_load_factor(load_factor),
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+    pub fn insert(&mut self, value: T) -> bool {
+        self.map.insert(value, ()).is_none()
+    }
+    pub fn contains(&self, value: &T) -> bool {
+        self.map.get(value).is_some()
+    }
+    pub fn remove(&mut self, value: &T) -> bool {
+        self.map.remove(value).is_some()
+    }
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = &T> {
+        self.map.iter().map(|(value, _)| value)
+    }
+    pub fn union<'a>(&'a self, other: &'a Self) -> impl Iterator<Item = &'a T> {
+        self.iter().chain(other.difference(self))
+    }
+    pub fn intersection<'a>(&'a self, other: &'a Self) -> impl Iterator<Item = &'a T> {
+        self.iter().filter(move |value| other.contains(value))
+    }
+    pub fn difference<'a>(&'a self, other: &'a Self) -> impl Iterator<Item = &'a T> {
+        self.iter().filter(move |value| !other.contains(value))
+    }
+}
+
+impl<T: Hash + Eq> Default for HashSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -457,7 +911,7 @@ mod tests {
     fn test_rehash_large() {
         let mut table = HashMap::with_capacity(1);
         table.insert("hello".to_string(), 42);
-        assert_eq!(table.buckets.len(), 16);
+        assert_eq!(table.buckets.len(), 4);
         for i in 0..1000 {
             table.insert(i.to_string(), i);
         }
@@ -472,4 +926,693 @@ mod tests {
         }
         assert_eq!(table.len(), 100_000);
     }
+
+    #[test]
+    fn test_get_copied() {
+        let mut table = HashMap::new();
+        for i in 0..100_u64 {
+            table.insert(i, i * 3);
+        }
+        for i in 0..200_u64 {
+            assert_eq!(table.get_copied(&i), table.get(&i).copied());
+        }
+        assert_eq!(table.get_copied(&7), Some(21));
+        assert_eq!(table.get_copied(&100), None);
+    }
+
+    #[test]
+    fn test_iter() {
+        let mut table = HashMap::new();
+        for i in 0..100 {
+            table.insert(i, i * 2);
+        }
+        table.remove(&50);
+        let iter = table.iter();
+        assert_eq!(iter.len(), 99);
+        let mut entries: Vec<_> = iter.map(|(&k, &v)| (k, v)).collect();
+        entries.sort();
+        let expected: Vec<_> = (0..100).filter(|&i| i != 50).map(|i| (i, i * 2)).collect();
+        assert_eq!(entries, expected);
+    }
+
+    fn overlapping_sets() -> (HashSet<i32>, HashSet<i32>) {
+        let mut a = HashSet::new();
+        let mut b = HashSet::new();
+        for i in 0..10 {
+            assert!(a.insert(i));
+            assert!(b.insert(i + 5));
+        }
+        assert!(!a.insert(0));
+        (a, b)
+    }
+
+    fn sorted<'a>(iter: impl Iterator<Item = &'a i32>) -> Vec<i32> {
+        let mut values: Vec<_> = iter.copied().collect();
+        values.sort();
+        values
+    }
+
+    #[test]
+    fn test_set() {
+        let (mut a, _) = overlapping_sets();
+        assert_eq!(a.len(), 10);
+        assert!(a.contains(&3));
+        assert!(!a.contains(&10));
+        assert!(a.remove(&3));
+        assert!(!a.remove(&3));
+        assert!(!a.contains(&3));
+        assert_eq!(sorted(a.iter()), vec![0, 1, 2, 4, 5, 6, 7, 8, 9]);
+        a.clear();
+        assert!(a.is_empty());
+    }
+
+    #[test]
+    fn test_set_union() {
+        let (a, b) = overlapping_sets();
+        assert_eq!(sorted(a.union(&b)), (0..15).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_set_intersection() {
+        let (a, b) = overlapping_sets();
+        assert_eq!(sorted(a.intersection(&b)), (5..10).collect::<Vec<_>>());
+        assert_eq!(sorted(b.intersection(&a)), (5..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_set_difference() {
+        let (a, b) = overlapping_sets();
+        assert_eq!(sorted(a.difference(&b)), (0..5).collect::<Vec<_>>());
+        assert_eq!(sorted(b.difference(&a)), (10..15).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_get_many() {
+        let mut table = HashMap::new();
+        for i in 0..1000 {
+            table.insert(i, i * 2);
+        }
+        let keys: Vec<_> = (500..1500).rev().collect();
+        let results = table.get_many(&keys);
+        assert_eq!(results.len(), keys.len());
+        for (key, result) in keys.iter().zip(results) {
+            assert_eq!(result, table.get(key));
+        }
+        let empty: HashMap<i32, i32> = HashMap::new();
+        assert_eq!(empty.get_many(&[1, 2]), vec![None, None]);
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn test_rehash_capacity_overflow() {
+        scaled_capacity(usize::MAX / 2 + 1, 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn test_with_capacity_overflow() {
+        HashMap::<u8, u8>::with_capacity(usize::MAX / 4);
+    }
+
+    #[test]
+    fn test_into_iter() {
+        let mut table = HashMap::new();
+        for i in 0..1000 {
+            table.insert(i.to_string(), i);
+        }
+        let iter = table.into_iter();
+        assert_eq!(iter.len(), 1000);
+        let mut entries: Vec<(String, i32)> = iter.collect();
+        entries.sort_by_key(|(_, value)| *value);
+        let expected: Vec<_> = (0..1000).map(|i| (i.to_string(), i)).collect();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn test_into_iter_partial_drop() {
+        let shared = std::rc::Rc::new(());
+        let mut table = HashMap::new();
+        for i in 0..100 {
+            table.insert(i, shared.clone());
+        }
+        assert_eq!(std::rc::Rc::strong_count(&shared), 101);
+        let mut iter = table.into_iter();
+        let taken: Vec<_> = iter.by_ref().take(10).collect();
+        assert_eq!(iter.len(), 90);
+        drop(iter);
+        assert_eq!(std::rc::Rc::strong_count(&shared), 11);
+        drop(taken);
+        assert_eq!(std::rc::Rc::strong_count(&shared), 1);
+    }
+
+    #[test]
+    fn test_load_factor() {
+        let table: HashMap<i32, i32> = HashMap::with_load_factor(0.7);
+        assert_eq!(table.load_factor(), 0.7);
+        assert_eq!(table.fill_factor(), 0.0);
+    }
+
+    #[test]
+    fn test_try_insert() {
+        let mut table = HashMap::new();
+        assert_eq!(table.try_insert(1, "one").unwrap(), &mut "one");
+        *table.try_insert(2, "two").unwrap() = "second";
+        let error = table.try_insert(1, "uno").unwrap_err();
+        assert_eq!(error.key, 1);
+        assert_eq!(error.value, "uno");
+        assert_eq!(error.current, &mut "one");
+        *error.current = "first";
+        assert_eq!(table.get(&1), Some(&"first"));
+        assert_eq!(table.get(&2), Some(&"second"));
+        assert_eq!(table.len(), 2);
+
+        let mut table = HashMap::new();
+        for i in 0..1000 {
+            assert_eq!(table.try_insert(i, i.to_string()).unwrap(), &i.to_string());
+        }
+        assert_eq!(table.len(), 1000);
+        for i in 0..1000 {
+            assert_eq!(table.get(&i), Some(&i.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_try_insert_with_kicks() {
+        let mut table = HashMap::with_load_factor(0.5);
+        for i in 0..10_000 {
+            let value = table.try_insert(i, i).unwrap();
+            assert_eq!(*value, i);
+            *value += 1;
+        }
+        assert_eq!(table.len(), 10_000);
+        for i in 0..10_000 {
+            assert_eq!(table.get(&i), Some(&(i + 1)));
+        }
+    }
+
+    #[test]
+    fn test_candidate_indices() {
+        let mut table = HashMap::new();
+        for i in 0..1000 {
+            table.insert(i, i);
+        }
+        let half = table.buckets.len() / 2;
+        for i in 0..1000 {
+            let (index_a, index_b) = table.candidate_indices(&i);
+            assert!(index_a < half && index_b < half);
+            let in_a = matches!(&table.buckets[index_a], Some(entry) if entry.key == i);
+            let in_b = matches!(&table.buckets[half + index_b], Some(entry) if entry.key == i);
+            assert!(in_a != in_b);
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    struct CaseInsensitive(&'static str);
+
+    impl PartialEq for CaseInsensitive {
+        fn eq(&self, other: &Self) -> bool {
+            self.0.eq_ignore_ascii_case(other.0)
+        }
+    }
+
+    impl Eq for CaseInsensitive {}
+
+    impl Hash for CaseInsensitive {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.0.to_ascii_lowercase().hash(state);
+        }
+    }
+
+    #[test]
+    fn test_overwrite_keeps_new_key() {
+        let mut table = HashMap::new();
+        assert_eq!(table.insert(CaseInsensitive("Foo"), 1), None);
+        assert_eq!(table.insert(CaseInsensitive("foo"), 2), Some(1));
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.iter().next().unwrap().0 .0, "foo");
+    }
+
+    #[test]
+    fn test_capacity() {
+        let table: HashMap<i32, i32> = HashMap::new();
+        assert_eq!(table.capacity(), 0);
+        let mut table = HashMap::with_capacity(10_000);
+        assert!(table.capacity() >= 10_000);
+        let bucket_count = table.buckets.len();
+        assert_eq!(bucket_count, 25_000);
+        for i in 0..10_000 {
+            table.insert(i, i);
+            assert_eq!(table.buckets.len(), bucket_count);
+        }
+        table.insert(10_000, 10_000);
+        assert!(table.buckets.len() > bucket_count);
+    }
+
+    #[test]
+    fn test_replace_value() {
+        let mut table = HashMap::new();
+        assert_eq!(table.replace_value(&1, 10), None);
+        assert_eq!(table.get(&1), None);
+        for i in 0..100 {
+            table.insert(i, i);
+        }
+        for i in 0..100 {
+            assert_eq!(table.replace_value(&i, i + 1000), Some(i));
+        }
+        assert_eq!(table.replace_value(&100, 0), None);
+        assert_eq!(table.get(&100), None);
+        assert_eq!(table.len(), 100);
+        for i in 0..100 {
+            assert_eq!(table.get(&i), Some(&(i + 1000)));
+        }
+    }
+
+    #[test]
+    fn test_collisions() {
+        let mut table = HashMap::with_exact_capacity(8, 0.5);
+        let mut inserted = 0;
+        while table.collisions() == 0 {
+            table.insert(inserted, inserted);
+            inserted += 1;
+            assert!(inserted < 1000);
+        }
+        let collisions = table.collisions();
+        table.insert(0, 0);
+        assert_eq!(table.collisions(), collisions);
+        for i in inserted..1000 {
+            table.insert(i, i);
+        }
+        assert!(table.collisions() > collisions);
+        let collisions = table.collisions();
+        assert!(collisions < 1000);
+        for i in 0..1000 {
+            assert_eq!(table.get(&i), Some(&i));
+        }
+        table.clear();
+        assert_eq!(table.collisions(), 0);
+    }
+
+    #[test]
+    fn test_iter_mut() {
+        let mut table = HashMap::new();
+        for i in 0..100 {
+            table.insert(i, i);
+        }
+        table.remove(&50);
+        let iter = table.iter_mut();
+        assert_eq!(iter.len(), 99);
+        for (_, value) in iter {
+            *value = -*value;
+        }
+        for i in 0..100 {
+            if i == 50 {
+                assert_eq!(table.get(&i), None);
+            } else {
+                assert_eq!(table.get(&i), Some(&-i));
+            }
+        }
+    }
+
+    #[test]
+    fn test_two_buckets_after_clear() {
+        let mut table = HashMap::with_exact_capacity(2, 1.0);
+        table.insert(1, 10);
+        table.insert(2, 20);
+        assert_eq!(table.buckets.len(), 2);
+        table.clear();
+        assert_eq!(table.buckets.len(), 2);
+        assert_eq!(table.get(&1), None);
+        assert_eq!(table.get_mut(&2), None);
+        assert_eq!(table.remove(&1), None);
+        table.insert(3, 30);
+        assert_eq!(table.get(&3), Some(&30));
+        assert_eq!(table.get(&1), None);
+        assert_eq!(table.get(&2), None);
+        *table.get_mut(&3).unwrap() += 1;
+        assert_eq!(table.get(&3), Some(&31));
+        table.insert(4, 40);
+        assert_eq!(table.buckets.len(), 2);
+        assert_eq!(table.get(&3), Some(&31));
+        assert_eq!(table.get(&4), Some(&40));
+    }
+
+    #[test]
+    fn test_two_buckets_after_removing_last() {
+        let mut table = HashMap::with_exact_capacity(2, 1.0);
+        table.insert(1, 10);
+        assert_eq!(table.remove(&1), Some(10));
+        assert!(table.is_empty());
+        assert_eq!(table.get(&1), None);
+        assert_eq!(table.get_mut(&1), None);
+        assert_eq!(table.remove(&1), None);
+        table.insert(2, 20);
+        assert_eq!(table.get(&2), Some(&20));
+        assert_eq!(table.get(&1), None);
+        assert_eq!(table.remove(&2), Some(20));
+        assert_eq!(table.get(&2), None);
+    }
+
+    #[test]
+    fn test_odd_bucket_count_after_clear() {
+        let mut table = HashMap::with_exact_capacity(3, 0.5);
+        let (index_a, index_b) = table.candidate_indices(&7);
+        assert_eq!(index_a, 0);
+        assert!(index_b < 2);
+        table.insert(7, 70);
+        table.clear();
+        assert_eq!(table.get(&7), None);
+        table.insert(8, 80);
+        assert_eq!(table.get(&8), Some(&80));
+        assert_eq!(table.get(&7), None);
+    }
+
+    #[test]
+    fn test_extend_grows_once() {
+        let mut table = HashMap::new();
+        table.insert(-1, -1);
+        table.extend((0..10_000).map(|i| (i, i * 2)));
+        assert_eq!(table.buckets.len(), even_bucket_count(10_001, 0.4));
+        assert_eq!(table.len(), 10_001);
+        assert_eq!(table.get(&-1), Some(&-1));
+        for i in 0..10_000 {
+            assert_eq!(table.get(&i), Some(&(i * 2)));
+        }
+    }
+
+    #[test]
+    fn test_verify_invariants() {
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+        let mut table = HashMap::new();
+        table.verify_invariants();
+        for round in 0..20_000 {
+            let key = rng.gen_range(0..500);
+            if rng.gen_bool(0.6) {
+                table.insert(key, round);
+            } else {
+                table.remove(&key);
+            }
+            if round % 1000 == 0 {
+                table.verify_invariants();
+            }
+        }
+        table.verify_invariants();
+    }
+
+    #[test]
+    fn test_clear_and_reseed() {
+        fn placement(table: &HashMap<i32, i32>) -> Vec<Option<i32>> {
+            table
+                .buckets
+                .iter()
+                .map(|bucket| bucket.as_ref().map(|entry| entry.key))
+                .collect()
+        }
+        let mut table = HashMap::with_exact_capacity(1024, 0.4);
+        let fill = |table: &mut HashMap<i32, i32>| {
+            for i in 0..200 {
+                table.insert(i, i);
+            }
+        };
+        fill(&mut table);
+        let before = placement(&table);
+        table.clear();
+        fill(&mut table);
+        assert_eq!(placement(&table), before);
+        table.clear_and_reseed();
+        assert!(table.is_empty());
+        fill(&mut table);
+        assert_ne!(placement(&table), before);
+        assert_eq!(table.buckets.len(), 1024);
+        for i in 0..200 {
+            assert_eq!(table.get(&i), Some(&i));
+        }
+    }
+
+    #[test]
+    fn test_extract_if() {
+        let mut table = HashMap::with_load_factor(0.5);
+        for i in 0..1000 {
+            table.insert(i, i * 2);
+        }
+        let mut extracted: Vec<_> = table.extract_if(|key, _| key % 2 == 1).collect();
+        extracted.sort();
+        assert_eq!(
+            extracted,
+            (0..1000)
+                .filter(|i| i % 2 == 1)
+                .map(|i| (i, i * 2))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(table.len(), 500);
+        for i in 0..1000 {
+            if i % 2 == 1 {
+                assert_eq!(table.get(&i), None);
+            } else {
+                assert_eq!(table.get(&i), Some(&(i * 2)));
+            }
+        }
+        table.verify_invariants();
+    }
+
+    #[test]
+    fn test_capacity_before_first_insert() {
+        let mut table = HashMap::new();
+        assert_eq!(table.capacity(), 0);
+        table.insert(1, 1);
+        assert!(table.capacity() > 0);
+        assert!(table.capacity() >= table.len());
+    }
+
+    #[test]
+    fn test_lookup_cost() {
+        let mut table = HashMap::with_load_factor(0.5);
+        table.insert(0, 0);
+        let (index_a, _) = table.candidate_indices(&0);
+        let second = (1..)
+            .find(|key| table.candidate_indices(key).0 == index_a)
+            .unwrap();
+        table.insert(second, second);
+        assert_eq!(table.lookup_cost(&0), Some(1));
+        assert_eq!(table.lookup_cost(&second), Some(2));
+        let missing = (1..).find(|key| *key != second).unwrap();
+        assert_eq!(table.lookup_cost(&missing), None);
+        table.remove(&0);
+        assert_eq!(table.lookup_cost(&0), None);
+        assert_eq!(table.lookup_cost(&second), Some(2));
+    }
+
+    #[test]
+    fn test_zero_sized_value_layout() {
+        use std::mem::size_of;
+
+        assert_eq!(size_of::<Entry<u64, ()>>(), size_of::<u64>());
+        assert_eq!(size_of::<Option<Entry<String, ()>>>(), size_of::<String>());
+    }
+
+    #[test]
+    fn test_debug_layout() {
+        let mut table = HashMap::with_load_factor(0.5);
+        table.insert(0, 0);
+        let (index_a, _) = table.candidate_indices(&0);
+        let second = (1..)
+            .find(|key| table.candidate_indices(key).0 == index_a)
+            .unwrap();
+        table.insert(second, second);
+        let (_, index_b) = table.candidate_indices(&second);
+        let layout = table.debug_layout();
+        let (a, b) = layout.split_once("B:\n").unwrap();
+        assert!(a.starts_with("A:\n"));
+        assert_eq!(a.lines().count(), 33);
+        assert_eq!(b.lines().count(), 32);
+        assert!(a.contains(&format!("  {index_a}: Entry(0)\n")));
+        assert!(b.contains(&format!("  {index_b}: Entry({second})\n")));
+        assert_eq!(layout.matches("None").count(), 62);
+    }
+
+    #[test]
+    fn test_with_hashers() {
+        use std::collections::hash_map::RandomState;
+
+        let mut table = HashMap::with_hashers(RandomState::new(), RandomState::new());
+        for i in 0..5000 {
+            table.insert(i, i * 2);
+        }
+        for i in 0..5000 {
+            assert_eq!(table.get(&i), Some(&(i * 2)));
+        }
+        assert_eq!(table.get(&5000), None);
+        table.verify_invariants();
+    }
+
+    #[test]
+    fn test_with_identical_hashers() {
+        use std::hash::BuildHasherDefault;
+
+        let hasher = BuildHasherDefault::<DefaultHasher>::default();
+        let mut table = HashMap::with_hashers(hasher.clone(), hasher);
+        for i in 0..200 {
+            table.insert(i, i);
+        }
+        for i in 0..200 {
+            assert_eq!(table.get(&i), Some(&i));
+        }
+        for i in 0..100 {
+            assert_eq!(table.remove(&i), Some(i));
+        }
+        assert_eq!(table.len(), 100);
+        table.verify_invariants();
+    }
+
+    #[test]
+    fn test_kick_cycle_with_hashers_doubles() {
+        let state = SeededState { seed: 7 };
+        let keys: Vec<u64> = (0..)
+            .filter(|key| state.hash_one(key) % 32 == 0)
+            .take(3)
+            .collect();
+        let mut table = HashMap::with_hashers(state.clone(), state);
+        for &key in &keys {
+            table.insert(key, key);
+        }
+        assert_eq!(table.buckets.len(), 128);
+        assert_eq!(table.hasher_a.seed, 7);
+        for &key in &keys {
+            assert_eq!(table.get(&key), Some(&key));
+        }
+        table.verify_invariants();
+    }
+
+    #[test]
+    fn test_seeded_state_matches_cloned_hasher() {
+        for _ in 0..100 {
+            let state = SeededState::default();
+            let mut seeded = DefaultHasher::new();
+            seeded.write_u64(state.seed);
+            for key in 0..100u64 {
+                let mut cloned = seeded.clone();
+                key.hash(&mut cloned);
+                assert_eq!(state.hash_one(key), cloned.finish());
+            }
+        }
+    }
+
+    #[test]
+    fn test_kick_cycle_reseeds_at_same_size() {
+        // Equal seeds give every key the same index in both halves, so three keys sharing
+        // an index can only be placed with new hashers
+        let state = SeededState { seed: 7 };
+        let keys: Vec<u64> = (0..)
+            .filter(|key| state.hash_one(key) % 32 == 0)
+            .take(3)
+            .collect();
+        let mut table = HashMap::new();
+        table.hasher_a = state.clone();
+        table.hasher_b = state;
+        for &key in &keys {
+            table.insert(key, key);
+        }
+        assert_eq!(table.buckets.len(), 64);
+        assert_ne!(table.hasher_seeds(), (7, 7));
+        for &key in &keys {
+            assert_eq!(table.get(&key), Some(&key));
+        }
+        table.verify_invariants();
+    }
+
+    #[test]
+    fn test_shrink_to_fit_keeps_entries() {
+        let mut table = HashMap::with_load_factor(0.5);
+        for round in 0..20 {
+            for i in 0..4000 {
+                table.insert(i, i + round);
+            }
+            let kept = 4000 - round * 190;
+            for i in kept..4000 {
+                table.remove(&i);
+            }
+            let bucket_count = table.buckets.len();
+            table.shrink_to_fit();
+            assert!(table.buckets.len() < bucket_count);
+            assert_eq!(table.len(), kept as usize);
+            for i in 0..4000 {
+                let expected = (i < kept).then_some(i + round);
+                assert_eq!(table.get(&i).copied(), expected);
+            }
+            table.verify_invariants();
+        }
+        table.clear();
+        table.shrink_to_fit();
+        assert!(table.buckets.is_empty());
+        table.insert(1, 1);
+        assert_eq!(table.get(&1), Some(&1));
+    }
+
+    #[test]
+    fn test_shrink_from_seeds_keeps_seeds() {
+        let mut rng = rand::thread_rng();
+        let (seed_a, seed_b) = (rng.gen(), rng.gen());
+        let mut table = HashMap::from_seeds(seed_a, seed_b);
+        let mut replay = HashMap::from_seeds(seed_a, seed_b);
+        for table in [&mut table, &mut replay] {
+            for i in 0..4000 {
+                table.insert(i, i);
+            }
+            for i in 200..4000 {
+                table.remove(&i);
+            }
+            table.shrink_to_fit();
+        }
+        assert_eq!(table.hasher_seeds(), (seed_a, seed_b));
+        assert_eq!(table.debug_layout(), replay.debug_layout());
+        for i in 0..200 {
+            assert_eq!(table.get(&i), Some(&i));
+        }
+        table.verify_invariants();
+    }
+
+    #[test]
+    fn test_shrink_to_keeps_min_capacity() {
+        let mut table = HashMap::new();
+        for i in 0..1000 {
+            table.insert(i, i);
+        }
+        for i in 100..1000 {
+            table.remove(&i);
+        }
+        table.shrink_to(500);
+        assert!(table.capacity() >= 500);
+        let bucket_count = table.buckets.len();
+        table.shrink_to(5000);
+        assert_eq!(table.buckets.len(), bucket_count);
+        for i in 0..100 {
+            assert_eq!(table.get(&i), Some(&i));
+        }
+        table.verify_invariants();
+    }
+
+    #[test]
+    fn test_from_seeds_replays_placement() {
+        let mut rng = rand::thread_rng();
+        // A new table would reseed on a kick cycle, which the replay cannot follow
+        let mut table = HashMap::from_seeds(rng.gen(), rng.gen());
+        let keys: Vec<u32> = (0..3000).map(|_| rng.gen()).collect();
+        for &key in &keys {
+            table.insert(key, key);
+        }
+        for key in keys.iter().step_by(5) {
+            table.remove(key);
+        }
+        let (seed_a, seed_b) = table.hasher_seeds();
+        let mut replay = HashMap::from_seeds(seed_a, seed_b);
+        for &key in &keys {
+            replay.insert(key, key);
+        }
+        for key in keys.iter().step_by(5) {
+            replay.remove(key);
+        }
+        assert_eq!(replay.hasher_seeds(), (seed_a, seed_b));
+        assert_eq!(replay.debug_layout(), table.debug_layout());
+        assert!(replay.iter().eq(table.iter()));
+    }
 }
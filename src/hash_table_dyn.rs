@@ -0,0 +1,112 @@
+use std::hash::Hash;
+
+use crate::{chaining_hashing, cuckoo_hashing, open_hashing, quad_cuckoo_hashing};
+
+// Object safe view of the single-threaded maps, to pick an implementation at runtime
+pub trait HashTableDyn<K, V> {
+    fn insert(&mut self, key: K, value: V) -> Option<V>;
+    fn get(&self, key: &K) -> Option<&V>;
+    fn get_mut(&mut self, key: &K) -> Option<&mut V>;
+    fn remove(&mut self, key: &K) -> Option<V>;
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool;
+    fn clear(&mut self);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableKind {
+    Chaining,
+    Open,
+    Cuckoo,
+    QuadCuckoo,
+}
+
+impl TableKind {
+    pub const ALL: [TableKind; 4] = [
+        TableKind::Chaining,
+        TableKind::Open,
+        TableKind::Cuckoo,
+        TableKind::QuadCuckoo,
+    ];
+}
+
+pub fn make<K: Hash + Eq + 'static, V: 'static>(kind: TableKind) -> Box<dyn HashTableDyn<K, V>> {
+    match kind {
+        TableKind::Chaining => Box::new(chaining_hashing::HashMap::new()),
+        TableKind::Open => Box::new(open_hashing::HashMap::new()),
+        TableKind::Cuckoo => Box::new(cuckoo_hashing::HashMap::new()),
+        TableKind::QuadCuckoo => Box::new(quad_cuckoo_hashing::HashMap::new()),
+    }
+}
+
+macro_rules! impl_hash_table_dyn {
+    ($($map:ty),*) => {
+        $(
+            impl<K: Hash + Eq, V> HashTableDyn<K, V> for $map {
+                fn insert(&mut self, key: K, value: V) -> Option<V> {
+                    <$map>::insert(self, key, value)
+                }
+                fn get(&self, key: &K) -> Option<&V> {
+                    <$map>::get(self, key)
+                }
+                fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+                    <$map>::get_mut(self, key)
+                }
+                fn remove(&mut self, key: &K) -> Option<V> {
+                    <$map>::remove(self, key)
+                }
+                fn len(&self) -> usize {
+                    <$map>::len(self)
+                }
+                fn is_empty(&self) -> bool {
+                    <$map>::is_empty(self)
+                }
+                fn clear(&mut self) {
+                    <$map>::clear(self)
+                }
+            }
+        )*
+    };
+}
+
+impl_hash_table_dyn!(
+    chaining_hashing::HashMap<K, V>,
+    open_hashing::HashMap<K, V>,
+    cuckoo_hashing::HashMap<K, V>,
+    quad_cuckoo_hashing::HashMap<K, V>
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_all_kinds() {
+        for kind in TableKind::ALL {
+            let mut table: Box<dyn HashTableDyn<u64, u64>> = make(kind);
+            assert!(table.is_empty(), "{kind:?}");
+            for i in 0..10_000 {
+                assert_eq!(table.insert(i, i), None, "{kind:?}");
+            }
+            assert_eq!(table.insert(7, 70), Some(7), "{kind:?}");
+            *table.get_mut(&8).unwrap() = 80;
+            assert_eq!(table.len(), 10_000, "{kind:?}");
+            for i in (0..10_000).step_by(2) {
+                assert!(table.remove(&i).is_some(), "{kind:?}");
+            }
+            assert_eq!(table.remove(&0), None, "{kind:?}");
+            assert_eq!(table.len(), 5_000, "{kind:?}");
+            for i in 0..10_000 {
+                let expected = match i {
+                    _ if i % 2 == 0 => None,
+                    7 => Some(70),
+                    _ => Some(i),
+                };
+                assert_eq!(table.get(&i).copied(), expected, "{kind:?}");
+            }
+            table.clear();
+            assert!(table.is_empty(), "{kind:?}");
+            assert_eq!(table.get(&1), None, "{kind:?}");
+        }
+    }
+}
@@ -3,5 +3,6 @@ pub mod chaining_hashing_concurrent;
 pub mod chaining_hashing_concurrent_optimized;
 pub mod chaining_hashing_concurrent_optimized_2;
 pub mod cuckoo_hashing;
+pub mod hash_table_dyn;
 pub mod open_hashing;
 pub mod quad_cuckoo_hashing;
@@ -1,13 +1,31 @@
 use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
-use std::mem;
+use std::fmt::{Debug, Write};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::{Mutex, PoisonError};
+use std::{mem, slice, vec};
 
-pub struct HashMap<K: Hash + Eq, V> {
+use rand::Rng;
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+pub struct HashMap<K: Hash + Eq, V, S = SeededState> {
     buckets: Vec<Bucket<K, V>>,
     length: usize,
     tomb_count: usize,
     load_factor: f64,
+    tomb_threshold: f64,
+    hasher: S,
+    on_rehash: Option<RehashHook>,
+    // Only enforced by try_insert_bounded, the other inserts keep growing past it
+    max_capacity: Option<usize>,
 }
+
+// Observes every rebuild of the buckets as (old bucket count, new bucket count), after the
+// entries were moved. Tomb purges at the same size are reported as well. The Mutex keeps the
+// map Sync without asking Sync of the hook, it is only reached through &mut self and never locked.
+type RehashHook = Mutex<Box<dyn FnMut(usize, usize) + Send>>;
+
 enum Bucket<K: Hash + Eq, V> {
     None,
     Entry(Entry<K, V>),
@@ -17,6 +35,86 @@ enum Bucket<K: Hash + Eq, V> {
 struct Entry<K: Hash + Eq, V> {
     key: K,
     value: V,
+    // Full hash of the key, compared before the key itself and reused when rehashing
+    hash: u64,
+}
+
+// Read-only table packed at a high load factor, without tombs or spare capacity
+pub struct FrozenOpenHashMap<K: Hash + Eq, V, S = SeededState> {
+    buckets: Box<[Bucket<K, V>]>,
+    length: usize,
+    hasher: S,
+}
+
+// A DefaultHasher keyed with a per-table seed, so crafted keys can't target the probe sequences
+// of all tables while with_seed still reproduces a layout
+#[derive(Clone)]
+pub struct SeededState {
+    seed: u64,
+}
+
+pub struct Iter<'a, K: Hash + Eq, V> {
+    buckets: slice::Iter<'a, Bucket<K, V>>,
+    remaining: usize,
+}
+
+// Yields entries in bucket order, reading the buffer sequentially
+pub struct IntoIter<K: Hash + Eq, V> {
+    buckets: vec::IntoIter<Bucket<K, V>>,
+    remaining: usize,
+}
+
+// Yields entries in bucket order and empties every bucket, even when dropped early
+pub struct Drain<'a, K: Hash + Eq, V> {
+    buckets: slice::IterMut<'a, Bucket<K, V>>,
+    remaining: usize,
+}
+
+// Free slot on the probe path of an absent key, nothing is stored unless insert is called
+pub struct VacantSlot<'a, K: Hash + Eq, V> {
+    key: K,
+    hash: u64,
+    bucket: &'a mut Bucket<K, V>,
+    length: &'a mut usize,
+    tomb_count: &'a mut usize,
+}
+
+#[derive(Debug)]
+pub struct DuplicateKeyError<K> {
+    pub key: K,
+}
+
+#[derive(Debug)]
+pub struct OccupiedError<'a, K, V> {
+    pub key: K,
+    pub value: V,
+    pub current: &'a mut V,
+}
+
+// The largest load factor below 1.0, requested load factors of 1.0 and above are clamped to it.
+// Inserts grow before the last empty bucket is taken either way, see needs_growth.
+const MAX_LOAD_FACTOR: f64 = 1.0 - f64::EPSILON / 2.0;
+
+fn scaled_capacity(capacity: usize, factor: usize) -> usize {
+    capacity.checked_mul(factor).expect("capacity overflow")
+}
+
+impl Default for SeededState {
+    fn default() -> Self {
+        Self {
+            seed: rand::thread_rng().gen::<u64>(),
+        }
+    }
+}
+
+impl BuildHasher for SeededState {
+    type Hasher = DefaultHasher;
+
+    fn build_hasher(&self) -> DefaultHasher {
+        let mut hasher = DefaultHasher::new();
+        hasher.write_u64(self.seed);
+        hasher
+    }
 }
 
 impl<K: Hash + Eq, V> HashMap<K, V> {
@@ -24,17 +122,68 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
         Self::with_exact_capacity(0, 0.4)
     }
     pub fn with_capacity(capacity: usize) -> Self {
-        Self::with_exact_capacity(capacity * 8, 0.4)
+        Self::with_exact_capacity(scaled_capacity(capacity, 8), 0.4)
+    }
+    pub fn from_pairs_checked<I: IntoIterator<Item = (K, V)>>(
+        iter: I,
+    ) -> Result<Self, DuplicateKeyError<K>> {
+        let mut table = Self::new();
+        for (key, value) in iter {
+            if let Err(error) = table.try_insert(key, value) {
+                return Err(DuplicateKeyError { key: error.key });
+            }
+        }
+        Ok(table)
+    }
+    // The fewest buckets that take n inserts without a rehash, with no extra headroom:
+    // the insert after the n-th one rehashes
+    pub fn with_exact_entries(n: usize, load_factor: f64) -> Self {
+        let load_factor = load_factor.min(MAX_LOAD_FACTOR);
+        let bucket_count = if n == 0 {
+            0
+        } else {
+            let bucket_count = ((n - 1) as f64 / load_factor).floor();
+            assert!(bucket_count < usize::MAX as f64, "capacity overflow");
+            // The n-th insert must still leave an empty bucket behind
+            (bucket_count as usize + 1).max(n.checked_add(1).expect("capacity overflow"))
+        };
+        Self::with_exact_capacity(bucket_count, load_factor)
+    }
+    pub fn with_max_capacity(max_capacity: usize) -> Self {
+        Self {
+            max_capacity: Some(max_capacity),
+            ..Self::new()
+        }
     }
     pub fn with_load_factor(load_factor: f64) -> Self {
-        Self::with_exact_capacity(0, load_factor)
+        Self::with_exact_capacity(0, load_factor.min(MAX_LOAD_FACTOR))
     }
     fn with_exact_capacity(capacity: usize, load_factor: f64) -> Self {
+        Self::with_exact_capacity_and_hasher(capacity, load_factor, SeededState::default())
+    }
+    // Same seed, same layout for the same inserts, new() picks a random seed per table
+    pub fn with_seed(seed: u64) -> Self {
+        Self::with_hasher(SeededState { seed })
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> HashMap<K, V, S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        Self::with_exact_capacity_and_hasher(0, 0.4, hasher)
+    }
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        Self::with_exact_capacity_and_hasher(scaled_capacity(capacity, 8), 0.4, hasher)
+    }
+    fn with_exact_capacity_and_hasher(capacity: usize, load_factor: f64, hasher: S) -> Self {
         Self {
             buckets: (0..capacity).map(|_| Bucket::None).collect(),
             length: 0,
             tomb_count: 0,
             load_factor,
+            tomb_threshold: 0.2,
+            hasher,
+            on_rehash: None,
+            max_capacity: None,
         }
     }
     pub fn len(&self) -> usize {
@@ -43,6 +192,13 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
     pub fn is_empty(&self) -> bool {
         self.length == 0
     }
+    // Zero for a new table, its buckets are only allocated by the first insert
+    pub fn capacity(&self) -> usize {
+        (self.buckets.len() as f64 * self.load_factor) as usize
+    }
+    pub fn load_factor(&self) -> f64 {
+        self.load_factor
+    }
     pub fn fill_factor(&self) -> f64 {
         if self.buckets.is_empty() {
             0.0
@@ -50,6 +206,71 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
             self.length as f64 / self.buckets.len() as f64
         }
     }
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            buckets: self.buckets.iter(),
+            remaining: self.length,
+        }
+    }
+    pub fn keys(&self) -> impl ExactSizeIterator<Item = &K> {
+        self.iter().map(|(key, _)| key)
+    }
+    pub fn values(&self) -> impl ExactSizeIterator<Item = &V> {
+        self.iter().map(|(_, value)| value)
+    }
+    pub fn sorted_entries_mut(&mut self) -> Vec<(&K, &mut V)>
+    where
+        K: Ord,
+    {
+        let mut entries: Vec<_> = self
+            .buckets
+            .iter_mut()
+            .filter_map(|bucket| match bucket {
+                Bucket::Entry(entry) => Some((&entry.key, &mut entry.value)),
+                _ => None,
+            })
+            .collect();
+        entries.sort_unstable_by_key(|(key, _)| *key);
+        entries
+    }
+    // One line per bucket in probe order, to make clusters and tombs visible
+    pub fn debug_layout(&self) -> String
+    where
+        K: Debug,
+    {
+        let mut layout = String::new();
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            match bucket {
+                Bucket::None => writeln!(layout, "{index}: None"),
+                Bucket::Entry(entry) => writeln!(layout, "{index}: Entry({:?})", entry.key),
+                Bucket::Tomb => writeln!(layout, "{index}: Tomb"),
+            }
+            .unwrap();
+        }
+        layout
+    }
+
+    // A single scan over the buckets instead of a lookup per key. Returns how many values f saw
+    pub fn update_matching<P: Fn(&K) -> bool, F: FnMut(&mut V)>(
+        &mut self,
+        pred: P,
+        mut f: F,
+    ) -> usize {
+        let mut updated = 0;
+        for bucket in self.buckets.iter_mut() {
+            if let Bucket::Entry(entry) = bucket {
+                if pred(&entry.key) {
+                    f(&mut entry.value);
+                    updated += 1;
+                }
+            }
+        }
+        updated
+    }
+    // Tomb factor above which remove_and_hint suggests compacting
+    pub fn set_tomb_threshold(&mut self, tomb_threshold: f64) {
+        self.tomb_threshold = tomb_threshold;
+    }
     fn tomb_factor(&self) -> f64 {
         if self.buckets.is_empty() {
             0.0
@@ -57,208 +278,796 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
             self.tomb_count as f64 / self.buckets.len() as f64
         }
     }
+    pub fn drain(&mut self) -> Drain<'_, K, V> {
+        let remaining = mem::take(&mut self.length);
+        self.tomb_count = 0;
+        Drain {
+            buckets: self.buckets.iter_mut(),
+            remaining,
+        }
+    }
     pub fn clear(&mut self) {
         self.length = 0;
+        self.tomb_count = 0;
         for element in self.buckets.iter_mut() {
             *element = Bucket::None;
         }
     }
 
-    fn calculate_hash(key: &K) -> u64 {
-        let mut s = DefaultHasher::new();
-        key.hash(&mut s);
-        s.finish()
+    fn calculate_hash(key: &K, hasher: &S) -> u64 {
+        hasher.hash_one(key)
     }
 
     pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.insert_tracked(key, value).0
+    }
+
+    pub fn insert_tracked(&mut self, key: K, value: V) -> (Option<V>, bool) {
+        let rehashed = self.grow_if_needed();
+        (self.place(key, value).0, rehashed)
+    }
+
+    // The index is only valid until the next mutable call, even get_mut may move the entry
+    pub fn insert_indexed(&mut self, key: K, value: V) -> (Option<V>, usize) {
+        self.grow_if_needed();
+        self.place(key, value)
+    }
+
+    // Grows like insert, but never past the bucket count that holds max_capacity entries.
+    // A new key beyond max_capacity entries is handed back instead.
+    pub fn try_insert_bounded(&mut self, key: K, value: V) -> Result<Option<V>, (K, V)> {
+        let Some(max_capacity) = self.max_capacity else {
+            return Ok(self.insert(key, value));
+        };
+        if !self.buckets.is_empty() {
+            let hash = Self::calculate_hash(&key, &self.hasher);
+            if self.find_entry(&key, hash).is_some() {
+                return Ok(self.place(key, value).0);
+            }
+        }
+        if self.length >= max_capacity {
+            return Err((key, value));
+        }
+        if self.buckets.is_empty() || self.needs_growth() {
+            let limit = (max_capacity as f64 / self.load_factor) as usize + 1;
+            let bucket_count = match self.buckets.len() {
+                0 => 64,
+                old_bucket_count => scaled_capacity(old_bucket_count, 2),
+            };
+            self.resize(bucket_count.min(limit).max(self.buckets.len()));
+        }
+        Ok(self.place(key, value).0)
+    }
+
+    pub fn get_at(&self, index: usize) -> Option<(&K, &V)> {
+        match self.buckets.get(index)? {
+            Bucket::Entry(entry) => Some((&entry.key, &entry.value)),
+            _ => None,
+        }
+    }
+
+    fn place(&mut self, key: K, value: V) -> (Option<V>, usize) {
+        let hash = Self::calculate_hash(&key, &self.hasher);
+        match self.find_slot(&key, hash) {
+            Ok(index) => match &mut self.buckets[index] {
+                Bucket::Entry(old_entry) => {
+                    let old = mem::replace(old_entry, Entry { key, value, hash });
+                    (Some(old.value), index)
+                }
+                _ => unreachable!("bucket is not an entry"),
+            },
+            Err(index) => {
+                self.occupy(index, Entry { key, value, hash });
+                (None, index)
+            }
+        }
+    }
+
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<&mut V, OccupiedError<'_, K, V>> {
+        self.grow_if_needed();
+        let hash = Self::calculate_hash(&key, &self.hasher);
+        match self.find_slot(&key, hash) {
+            Ok(index) => Err(OccupiedError {
+                key,
+                value,
+                current: self.value_at_mut(index),
+            }),
+            Err(index) => {
+                self.occupy(index, Entry { key, value, hash });
+                Ok(self.value_at_mut(index))
+            }
+        }
+    }
+
+    // Grows up front like insert, so the slot stays free for the key until it is committed
+    pub fn vacant_entry(&mut self, key: K) -> Result<VacantSlot<'_, K, V>, K> {
+        self.grow_if_needed();
+        let hash = Self::calculate_hash(&key, &self.hasher);
+        match self.find_slot(&key, hash) {
+            Ok(_) => Err(key),
+            Err(index) => Ok(VacantSlot {
+                key,
+                hash,
+                bucket: &mut self.buckets[index],
+                length: &mut self.length,
+                tomb_count: &mut self.tomb_count,
+            }),
+        }
+    }
+
+    // Like try_insert, but an occupied key just yields the value already stored for it
+    pub fn insert_or_get(&mut self, key: K, value: V) -> &V {
+        self.grow_if_needed();
+        let hash = Self::calculate_hash(&key, &self.hasher);
+        match self.find_slot(&key, hash) {
+            Ok(index) => self.value_at_mut(index),
+            Err(index) => {
+                self.occupy(index, Entry { key, value, hash });
+                self.value_at_mut(index)
+            }
+        }
+    }
+
+    pub fn get_mut_or_insert_default(&mut self, key: K) -> &mut V
+    where
+        V: Default,
+    {
+        self.grow_if_needed();
+        let hash = Self::calculate_hash(&key, &self.hasher);
+        let index = match self.find_slot(&key, hash) {
+            Ok(index) => index,
+            Err(index) => {
+                self.occupy(
+                    index,
+                    Entry {
+                        key,
+                        value: V::default(),
+                        hash,
+                    },
+                );
+                index
+            }
+        };
+        self.value_at_mut(index)
+    }
+
+    fn value_at_mut(&mut self, index: usize) -> &mut V {
+        match &mut self.buckets[index] {
+            Bucket::Entry(entry) => &mut entry.value,
+            _ => unreachable!("bucket is not an entry"),
+        }
+    }
+
+    fn grow_if_needed(&mut self) -> bool {
         if self.buckets.is_empty() {
             self.buckets = (0..64).map(|_| Bucket::None).collect();
         }
-        if self.fill_factor() + self.tomb_factor() >= self.load_factor {
+        if self.needs_growth() {
             self.rehash();
+            true
+        } else {
+            false
         }
-        let hash = Self::calculate_hash(&key);
+    }
+
+    // Also grows before an insert could take the last empty bucket, which ends every probe
+    // sequence, even when the load factor would still allow it
+    fn needs_growth(&self) -> bool {
+        self.fill_factor() + self.tomb_factor() >= self.load_factor
+            || self.length + self.tomb_count + 1 >= self.buckets.len()
+    }
+
+    // Probe for the key, returning its slot or the first free slot of its probe sequence
+    fn find_slot(&self, key: &K, hash: u64) -> Result<usize, usize> {
         let mut index = hash as usize % self.buckets.len();
-        let entry = Entry { key, value };
-        loop {
-            match self.buckets.get_mut(index) {
-                Some(bucket) => match bucket {
-                    Bucket::None => {
-                        self.length += 1;
-                        *bucket = Bucket::Entry(entry);
-                        break None;
-                    }
-                    Bucket::Tomb => {
-                        self.length += 1;
-                        self.tomb_count -= 1;
-                        *bucket = Bucket::Entry(entry);
-                        break None;
-                    }
-                    Bucket::Entry(old_entry) if old_entry.key == entry.key => {
-                        break Some(mem::replace(old_entry, entry).value);
-                    }
-                    Bucket::Entry(_) => {
-                        index = (index + 1) % self.buckets.len();
-                    }
-                },
-                _ => {
-                    unreachable!("index out of bounds");
+        let mut first_tomb = None;
+        for _ in 0..self.buckets.len() {
+            match &self.buckets[index] {
+                Bucket::None => return Err(first_tomb.unwrap_or(index)),
+                Bucket::Tomb => {
+                    first_tomb.get_or_insert(index);
                 }
+                Bucket::Entry(entry) if entry.hash == hash && entry.key == *key => {
+                    return Ok(index)
+                }
+                Bucket::Entry(_) => {}
             }
+            index = (index + 1) % self.buckets.len();
         }
+        Err(first_tomb.expect("table is full"))
     }
 
-    fn rehash(&mut self) {
-        let mut new_table = HashMap::with_exact_capacity(self.buckets.len() * 2, self.load_factor);
+    fn occupy(&mut self, index: usize, entry: Entry<K, V>) {
+        if let Bucket::Tomb = mem::replace(&mut self.buckets[index], Bucket::Entry(entry)) {
+            self.tomb_count -= 1;
+        }
+        self.length += 1;
+    }
+
+    // Tombs count against the load factor as well, so they are purged before reserving
+    pub fn reserve(&mut self, additional: usize) {
+        if additional == 0 {
+            return;
+        }
+        let entries = self
+            .length
+            .checked_add(additional)
+            .expect("capacity overflow");
+        let bucket_count = (entries as f64 / self.load_factor).floor();
+        assert!(bucket_count < usize::MAX as f64, "capacity overflow");
+        let bucket_count = bucket_count as usize + 1;
+        if bucket_count > self.buckets.len() {
+            self.resize(bucket_count);
+        } else if self.tomb_count > 0 {
+            self.compact_in_place();
+        }
+    }
+
+    // Returns how many of the pairs overwrote a key that was already present
+    pub fn insert_all<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) -> usize {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        let mut overwrites = 0;
+        for (key, value) in iter {
+            if self.insert(key, value).is_some() {
+                overwrites += 1;
+            }
+        }
+        overwrites
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        if self.length == 0 {
+            let old_bucket_count = mem::take(&mut self.buckets).len();
+            self.tomb_count = 0;
+            self.report_rehash(old_bucket_count);
+        } else {
+            self.resize((self.length as f64 / self.load_factor) as usize + 1);
+        }
+    }
+
+    // Purges tombs without a second allocation by re-placing every entry within the same buffer.
+    // Scanning starts after a slot that was empty before the purge, which no probe sequence
+    // crosses, so each entry only ever moves to an earlier slot of its own probe sequence.
+    pub fn compact_in_place(&mut self) {
+        let bucket_count = self.buckets.len();
+        let Some(start) = self
+            .buckets
+            .iter()
+            .position(|bucket| matches!(bucket, Bucket::None))
+        else {
+            self.resize(bucket_count);
+            return;
+        };
         for bucket in self.buckets.iter_mut() {
-            if let Bucket::Entry(entry) = mem::replace(bucket, Bucket::Tomb) {
-                new_table.insert(entry.key, entry.value);
+            if let Bucket::Tomb = bucket {
+                *bucket = Bucket::None;
+            }
+        }
+        self.tomb_count = 0;
+        for offset in 1..bucket_count {
+            let index = (start + offset) % bucket_count;
+            if let Bucket::Entry(entry) = mem::replace(&mut self.buckets[index], Bucket::None) {
+                let mut target = entry.hash as usize % bucket_count;
+                while !matches!(self.buckets[target], Bucket::None) {
+                    target = (target + 1) % bucket_count;
+                }
+                self.buckets[target] = Bucket::Entry(entry);
+            }
+        }
+        self.report_rehash(bucket_count);
+    }
+
+    pub fn set_rehash_hook(&mut self, f: impl FnMut(usize, usize) + Send + 'static) {
+        self.on_rehash = Some(Mutex::new(Box::new(f)));
+    }
+
+    fn report_rehash(&mut self, old_bucket_count: usize) {
+        let new_bucket_count = self.buckets.len();
+        if let Some(on_rehash) = &mut self.on_rehash {
+            on_rehash.get_mut().unwrap_or_else(PoisonError::into_inner)(
+                old_bucket_count,
+                new_bucket_count,
+            );
+        }
+    }
+
+    fn rehash(&mut self) {
+        self.resize(scaled_capacity(self.buckets.len(), 2));
+    }
+
+    fn resize(&mut self, bucket_count: usize) {
+        let old_buckets = mem::replace(
+            &mut self.buckets,
+            (0..bucket_count).map(|_| Bucket::None).collect(),
+        );
+        let old_bucket_count = old_buckets.len();
+        self.tomb_count = 0;
+        for bucket in old_buckets {
+            if let Bucket::Entry(entry) = bucket {
+                let mut index = entry.hash as usize % bucket_count;
+                while !matches!(self.buckets[index], Bucket::None) {
+                    index = (index + 1) % bucket_count;
+                }
+                self.buckets[index] = Bucket::Entry(entry);
             }
         }
-        mem::swap(self, &mut new_table);
+        self.report_rehash(old_bucket_count);
     }
 
     pub fn get(&self, key: &K) -> Option<&V> {
         if self.is_empty() {
             return None;
         }
-        let hash = Self::calculate_hash(key);
-        let index = hash as usize % self.buckets.len();
-        let (a, b) = self.buckets.split_at(index);
-        b.iter()
-            .chain(a.iter())
-            .take_while(|bucket| !matches!(bucket, Bucket::None))
-            .find_map(|bucket| match bucket {
-                Bucket::Entry(entry) if entry.key == *key => Some(&entry.value),
-                _ => None,
-            })
+        // find_entry gives up after one pass over the buckets, even if none of them is empty
+        let index = self.find_entry(key, Self::calculate_hash(key, &self.hasher))?;
+        match &self.buckets[index] {
+            Bucket::Entry(entry) => Some(&entry.value),
+            _ => unreachable!("bucket is not an entry"),
+        }
+    }
+
+    pub fn get_copied(&self, key: &K) -> Option<V>
+    where
+        V: Copy,
+    {
+        self.get(key).copied()
     }
 
+    // Moves the entry into the first tomb on its probe path, leaving a tomb in its place so
+    // the probe sequences of other entries stay intact
     pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
         if self.is_empty() {
             return None;
         }
-        let hash = Self::calculate_hash(key);
-        let index = hash as usize % self.buckets.len();
-        let (a, b) = self.buckets.split_at_mut(index);
-        b.iter_mut()
-            .chain(a.iter_mut())
-            .take_while(|bucket| !matches!(bucket, Bucket::None))
-            .find_map(|bucket| match bucket {
-                Bucket::Entry(entry) if entry.key == *key => Some(&mut entry.value),
-                _ => None,
-            })
+        let hash = Self::calculate_hash(key, &self.hasher);
+        let mut index = self.find_entry(key, hash)?;
+        if let Some(tomb) = self.first_tomb_before(hash, index) {
+            self.buckets.swap(tomb, index);
+            index = tomb;
+        }
+        Some(self.value_at_mut(index))
     }
 
-    pub fn remove(&mut self, key: &K) -> Option<V> {
+    // Slots a lookup of the key inspects, counting the one holding it
+    pub fn get_probe_length(&self, key: &K) -> Option<usize> {
         if self.is_empty() {
             return None;
         }
-        let hash = Self::calculate_hash(key);
-        let index = hash as usize % self.buckets.len();
-        let (a, b) = self.buckets.split_at_mut(index);
-        b.iter_mut()
-            .chain(a.iter_mut())
-            .take_while(|bucket| !matches!(bucket, Bucket::None))
-            .find_map(|bucket| match bucket {
-                Bucket::Entry(entry) if entry.key == *key => {
-                    self.length -= 1;
-                    if let Bucket::Entry(entry) = mem::replace(bucket, Bucket::Tomb) {
-                        self.tomb_count += 1;
-                        Some(entry.value)
-                    } else {
-                        unreachable!("bucket is not an entry");
-                    }
-                }
-                _ => None,
-            })
+        let hash = Self::calculate_hash(key, &self.hasher);
+        let index = self.find_entry(key, hash)?;
+        let home = hash as usize % self.buckets.len();
+        Some((index + self.buckets.len() - home) % self.buckets.len() + 1)
     }
-}
 
-impl<K: Hash + Eq, V> Default for HashMap<K, V> {
-    fn default() -> Self {
-        Self::new()
+    fn find_entry(&self, key: &K, hash: u64) -> Option<usize> {
+        let mut index = hash as usize % self.buckets.len();
+        for _ in 0..self.buckets.len() {
+            match &self.buckets[index] {
+                Bucket::None => return None,
+                Bucket::Entry(entry) if entry.hash == hash && entry.key == *key => {
+                    return Some(index)
+                }
+                _ => {}
+            }
+            index = (index + 1) % self.buckets.len();
+        }
+        None
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    fn first_tomb_before(&self, hash: u64, index: usize) -> Option<usize> {
+        let mut current = hash as usize % self.buckets.len();
+        while current != index {
+            if let Bucket::Tomb = self.buckets[current] {
+                return Some(current);
+            }
+            current = (current + 1) % self.buckets.len();
+        }
+        None
+    }
 
-    #[test]
-    fn test_new() {
-        let table: HashMap<i32, i32> = HashMap::new();
-        assert_eq!(table.len(), 0);
-        assert!(table.is_empty());
-        assert_eq!(table.fill_factor(), 0.0);
+    pub fn replace_value(&mut self, key: &K, value: V) -> Option<V> {
+        self.get_mut(key)
+            .map(|current| mem::replace(current, value))
     }
 
-    #[test]
-    fn test_with_capacity() {
-        let table: HashMap<i32, i32> = HashMap::with_capacity(10);
-        assert_eq!(table.len(), 0);
-        assert!(table.is_empty());
-        assert_eq!(table.fill_factor(), 0.0);
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        if self.is_empty() {
+            return None;
+        }
+        let hash = Self::calculate_hash(key, &self.hasher);
+        let index = self.find_entry(key, hash)?;
+        let bucket_count = self.buckets.len();
+        // No probe sequence continues past the end of a cluster, so no tomb is needed there
+        let end_of_cluster = matches!(self.buckets[(index + 1) % bucket_count], Bucket::None);
+        let removed = if end_of_cluster {
+            let removed = mem::replace(&mut self.buckets[index], Bucket::None);
+            let mut previous = (index + bucket_count - 1) % bucket_count;
+            while let Bucket::Tomb = self.buckets[previous] {
+                self.buckets[previous] = Bucket::None;
+                self.tomb_count -= 1;
+                previous = (previous + bucket_count - 1) % bucket_count;
+            }
+            removed
+        } else {
+            self.tomb_count += 1;
+            mem::replace(&mut self.buckets[index], Bucket::Tomb)
+        };
+        self.length -= 1;
+        match removed {
+            Bucket::Entry(entry) => Some(entry.value),
+            _ => unreachable!("bucket is not an entry"),
+        }
     }
 
-    #[test]
-    fn test_insert() {
-        let mut table = HashMap::with_exact_capacity(8, 0.75);
-        assert_eq!(table.insert(1, 10), None);
-        assert_eq!(table.len(), 1);
-        assert!(!table.is_empty());
-        assert_eq!(table.fill_factor(), 0.125);
-        assert_eq!(table.insert(1, 20), Some(10));
-        assert_eq!(table.len(), 1);
-        assert_eq!(table.fill_factor(), 0.125);
-        assert_eq!(table.insert(2, 30), None);
-        assert_eq!(table.len(), 2);
-        assert_eq!(table.fill_factor(), 0.25);
+    pub fn into_frozen(self) -> FrozenOpenHashMap<K, V, S> {
+        // Leaves at least one empty bucket, which keeps lookups of absent keys short
+        let bucket_count = (self.length as f64 / 0.9) as usize + 1;
+        let mut buckets: Box<[Bucket<K, V>]> = (0..bucket_count).map(|_| Bucket::None).collect();
+        for bucket in self.buckets {
+            if let Bucket::Entry(entry) = bucket {
+                let mut index = entry.hash as usize % bucket_count;
+                while !matches!(buckets[index], Bucket::None) {
+                    index = (index + 1) % bucket_count;
+                }
+                buckets[index] = Bucket::Entry(entry);
+            }
+        }
+        FrozenOpenHashMap {
+            buckets,
+            length: self.length,
+            hasher: self.hasher,
+        }
     }
 
-    #[test]
-    fn test_get() {
-        let mut table = HashMap::new();
-        table.insert(1, 10);
-        assert_eq!(table.get(&1), Some(&10));
-        assert_eq!(table.get(&2), None);
-        table.insert(2, 20);
-        assert_eq!(table.get(&1), Some(&10));
-        assert_eq!(table.get(&2), Some(&20));
+    pub fn remove_and_hint(&mut self, key: &K) -> (Option<V>, bool) {
+        let removed = self.remove(key);
+        (removed, self.tomb_factor() > self.tomb_threshold)
     }
 
-    #[test]
-    fn test_get_mut() {
-        let mut table = HashMap::new();
-        table.insert(1, 10);
-        assert_eq!(table.get_mut(&1), Some(&mut 10));
-        assert_eq!(table.get_mut(&2), None);
-        table.insert(2, 20);
-        assert_eq!(table.get_mut(&1), Some(&mut 10));
-        assert_eq!(table.get_mut(&2), Some(&mut 20));
-        *table.get_mut(&1).unwrap() = 30;
-        assert_eq!(table.get(&1), Some(&30));
+    #[cfg(any(test, feature = "invariant-checks"))]
+    pub fn verify_invariants(&self) {
+        let bucket_count = self.buckets.len();
+        let mut entries = 0;
+        let mut tombs = 0;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            match bucket {
+                Bucket::None => {}
+                Bucket::Tomb => tombs += 1,
+                Bucket::Entry(entry) => {
+                    entries += 1;
+                    assert_eq!(
+                        entry.hash,
+                        Self::calculate_hash(&entry.key, &self.hasher),
+                        "entry has a stale hash"
+                    );
+                    // The probe from the home slot must not hit an empty slot before the entry
+                    let mut current = entry.hash as usize % bucket_count;
+                    while current != index {
+                        assert!(
+                            !matches!(self.buckets[current], Bucket::None),
+                            "entry is unreachable from its home slot"
+                        );
+                        current = (current + 1) % bucket_count;
+                    }
+                }
+            }
+        }
+        assert_eq!(
+            entries, self.length,
+            "length does not match the entry count"
+        );
+        assert_eq!(
+            tombs, self.tomb_count,
+            "tomb_count does not match the tomb count"
+        );
     }
+}
 
-    #[test]
-    fn test_remove() {
-        let mut table = HashMap::new();
-        table.insert(1, 10);
-        assert_eq!(table.remove(&2), None);
-        assert_eq!(table.remove(&1), Some(10));
-        assert_eq!(table.len(), 0);
-        assert_eq!(table.fill_factor(), 0.0);
-        table.insert(1, 20);
-        table.insert(2, 30);
-        assert_eq!(table.remove(&1), Some(20));
-        assert_eq!(table.len(), 1);
-        assert_eq!(table.fill_factor(), 0.015625);
-        assert_eq!(table.remove(&2), Some(30));
-        assert_eq!(table.len(), 0);
-        assert_eq!(table.fill_factor(), 0.0);
+#[cfg(feature = "rayon")]
+impl<K: Hash + Eq + Send, V: Send, S: BuildHasher> HashMap<K, V, S> {
+    pub fn par_rehash(&mut self) {
+        self.par_resize(scaled_capacity(self.buckets.len(), 2));
     }
 
-    #[test]
+    // Each thread owns a disjoint range of the new buckets and places the entries homed there
+    // in home order. Entries whose probe runs past the end of their range are placed afterwards.
+    fn par_resize(&mut self, bucket_count: usize) {
+        let old_buckets = mem::take(&mut self.buckets);
+        let old_bucket_count = old_buckets.len();
+        self.tomb_count = 0;
+        if bucket_count == 0 {
+            self.report_rehash(old_bucket_count);
+            return;
+        }
+        let range_len = bucket_count
+            .div_ceil(rayon::current_num_threads() * 4)
+            .max(1);
+        let range_count = bucket_count.div_ceil(range_len);
+        let empty_groups = || (0..range_count).map(|_| Vec::new()).collect::<Vec<_>>();
+        let groups = old_buckets
+            .into_par_iter()
+            .filter_map(|bucket| match bucket {
+                Bucket::Entry(entry) => Some(entry),
+                _ => None,
+            })
+            .fold(empty_groups, |mut groups, entry| {
+                let home = entry.hash as usize % bucket_count;
+                groups[home / range_len].push((home, entry));
+                groups
+            })
+            .reduce(empty_groups, |mut left, right| {
+                for (left, right) in left.iter_mut().zip(right) {
+                    left.extend(right);
+                }
+                left
+            });
+        let mut buckets: Vec<Bucket<K, V>> = (0..bucket_count)
+            .into_par_iter()
+            .map(|_| Bucket::None)
+            .collect();
+        let spilled: Vec<(usize, Entry<K, V>)> = buckets
+            .par_chunks_mut(range_len)
+            .zip(groups)
+            .enumerate()
+            .flat_map_iter(|(range, (chunk, mut group))| {
+                group.sort_unstable_by_key(|(home, _)| *home);
+                let start = range * range_len;
+                let mut next_free = start;
+                let mut spilled = Vec::new();
+                for (home, entry) in group {
+                    let index = next_free.max(home);
+                    if index < start + chunk.len() {
+                        chunk[index - start] = Bucket::Entry(entry);
+                        next_free = index + 1;
+                    } else {
+                        spilled.push((home, entry));
+                    }
+                }
+                spilled
+            })
+            .collect();
+        for (mut index, entry) in spilled {
+            while !matches!(buckets[index], Bucket::None) {
+                index = (index + 1) % bucket_count;
+            }
+            buckets[index] = Bucket::Entry(entry);
+        }
+        self.buckets = buckets;
+        self.report_rehash(old_bucket_count);
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> FrozenOpenHashMap<K, V, S> {
+    pub fn len(&self) -> usize {
+        self.length
+    }
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            buckets: self.buckets.iter(),
+            remaining: self.length,
+        }
+    }
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let bucket_count = self.buckets.len();
+        let hash = HashMap::<K, V, S>::calculate_hash(key, &self.hasher);
+        let mut index = hash as usize % bucket_count;
+        loop {
+            match &self.buckets[index] {
+                Bucket::Entry(entry) if entry.hash == hash && entry.key == *key => {
+                    return Some(&entry.value)
+                }
+                Bucket::Entry(_) => index = (index + 1) % bucket_count,
+                _ => return None,
+            }
+        }
+    }
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+}
+
+impl<'a, K: Hash + Eq, V> VacantSlot<'a, K, V> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+    pub fn into_key(self) -> K {
+        self.key
+    }
+    pub fn insert(self, value: V) -> &'a mut V {
+        let entry = Entry {
+            key: self.key,
+            value,
+            hash: self.hash,
+        };
+        if let Bucket::Tomb = mem::replace(self.bucket, Bucket::Entry(entry)) {
+            *self.tomb_count -= 1;
+        }
+        *self.length += 1;
+        match self.bucket {
+            Bucket::Entry(entry) => &mut entry.value,
+            _ => unreachable!("bucket is not an entry"),
+        }
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Default> Default for HashMap<K, V, S> {
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> Extend<(K, V)> for HashMap<K, V, S> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: Hash + Eq, V, S> IntoIterator for HashMap<K, V, S> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            buckets: self.buckets.into_iter(),
+            remaining: self.length,
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.buckets.by_ref().find_map(|bucket| match bucket {
+            Bucket::Entry(entry) => Some(entry),
+            _ => None,
+        })?;
+        self.remaining -= 1;
+        Some((entry.key, entry.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K: Hash + Eq, V> ExactSizeIterator for IntoIter<K, V> {}
+
+impl<K: Hash + Eq, V> Iterator for Drain<'_, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry =
+            self.buckets
+                .by_ref()
+                .find_map(|bucket| match mem::replace(bucket, Bucket::None) {
+                    Bucket::Entry(entry) => Some(entry),
+                    _ => None,
+                })?;
+        self.remaining -= 1;
+        Some((entry.key, entry.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K: Hash + Eq, V> ExactSizeIterator for Drain<'_, K, V> {}
+
+impl<K: Hash + Eq, V> Drop for Drain<'_, K, V> {
+    fn drop(&mut self) {
+        for bucket in self.buckets.by_ref() {
+            *bucket = Bucket::None;
+        }
+    }
+}
+
+impl<'a, K: Hash + Eq, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.buckets.by_ref().find_map(|bucket| match bucket {
+            Bucket::Entry(entry) => Some(entry),
+            _ => None,
+        })?;
+        self.remaining -= 1;
+        Some((&entry.key, &entry.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K: Hash + Eq, V> ExactSizeIterator for Iter<'_, K, V> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new() {
+        let table: HashMap<i32, i32> = HashMap::new();
+        assert_eq!(table.len(), 0);
+        assert!(table.is_empty());
+        assert_eq!(table.fill_factor(), 0.0);
+    }
+
+    #[test]
+    fn test_with_capacity() {
+        let table: HashMap<i32, i32> = HashMap::with_capacity(10);
+        assert_eq!(table.len(), 0);
+        assert!(table.is_empty());
+        assert_eq!(table.fill_factor(), 0.0);
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut table = HashMap::with_exact_capacity(8, 0.75);
+        assert_eq!(table.insert(1, 10), None);
+        assert_eq!(table.len(), 1);
+        assert!(!table.is_empty());
+        assert_eq!(table.fill_factor(), 0.125);
+        assert_eq!(table.insert(1, 20), Some(10));
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.fill_factor(), 0.125);
+        assert_eq!(table.insert(2, 30), None);
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.fill_factor(), 0.25);
+    }
+
+    #[test]
+    fn test_get() {
+        let mut table = HashMap::new();
+        table.insert(1, 10);
+        assert_eq!(table.get(&1), Some(&10));
+        assert_eq!(table.get(&2), None);
+        table.insert(2, 20);
+        assert_eq!(table.get(&1), Some(&10));
+        assert_eq!(table.get(&2), Some(&20));
+    }
+
+    #[test]
+    fn test_get_mut() {
+        let mut table = HashMap::new();
+        table.insert(1, 10);
+        assert_eq!(table.get_mut(&1), Some(&mut 10));
+        assert_eq!(table.get_mut(&2), None);
+        table.insert(2, 20);
+        assert_eq!(table.get_mut(&1), Some(&mut 10));
+        assert_eq!(table.get_mut(&2), Some(&mut 20));
+        *table.get_mut(&1).unwrap() = 30;
+        assert_eq!(table.get(&1), Some(&30));
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut table = HashMap::new();
+        table.insert(1, 10);
+        assert_eq!(table.remove(&2), None);
+        assert_eq!(table.remove(&1), Some(10));
+        assert_eq!(table.len(), 0);
+        assert_eq!(table.fill_factor(), 0.0);
+        table.insert(1, 20);
+        table.insert(2, 30);
+        assert_eq!(table.remove(&1), Some(20));
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.fill_factor(), 0.015625);
+        assert_eq!(table.remove(&2), Some(30));
+        assert_eq!(table.len(), 0);
+        assert_eq!(table.fill_factor(), 0.0);
+    }
+
+    #[test]
     fn test_insert_multiple_entries() {
         let mut hash_table = HashMap::new();
 
@@ -409,16 +1218,18 @@ mod tests {
     #[test]
     fn test_tombs() {
         let mut table = HashMap::with_capacity(1);
-        table.insert("hello", 42);
-        table.remove(&"hello");
-        assert_eq!(table.get(&"hello"), None);
+        let hasher = table.hasher.clone();
+        let home = |key: &i32| HashMap::<i32, i32>::calculate_hash(key, &hasher) as usize % 8;
+        let collider = (1..).find(|i| home(i) == home(&0)).unwrap();
+        table.insert(0, 42);
+        table.insert(collider, 23);
+        table.remove(&0);
+        assert_eq!(table.get(&0), None);
         assert_eq!(table.tomb_count, 1);
-        table.insert("world", 23);
-        assert_eq!(table.get(&"world"), Some(&23));
-        assert_eq!(table.tomb_count, 1);
-        table.remove(&"world");
-        assert_eq!(table.get(&"world"), None);
-        assert_eq!(table.tomb_count, 2);
+        assert_eq!(table.get(&collider), Some(&23));
+        table.remove(&collider);
+        assert_eq!(table.get(&collider), None);
+        assert_eq!(table.tomb_count, 0);
     }
 
     #[test]
@@ -440,4 +1251,1104 @@ mod tests {
         }
         assert!(table.buckets.len() >= 2048);
     }
+
+    #[test]
+    fn test_get_copied() {
+        let mut table = HashMap::new();
+        for i in 0..100_u64 {
+            table.insert(i, i * 3);
+        }
+        for i in 0..200_u64 {
+            assert_eq!(table.get_copied(&i), table.get(&i).copied());
+        }
+        assert_eq!(table.get_copied(&7), Some(21));
+        assert_eq!(table.get_copied(&100), None);
+    }
+
+    #[test]
+    fn test_get_mut_or_insert_default() {
+        let mut counts = HashMap::new();
+        for word in "the quick brown fox jumps over the lazy dog the end".split(' ') {
+            *counts.get_mut_or_insert_default(word) += 1;
+        }
+        assert_eq!(counts.len(), 9);
+        assert_eq!(counts.get(&"the"), Some(&3));
+        assert_eq!(counts.get(&"fox"), Some(&1));
+        assert_eq!(counts.get(&"cat"), None);
+        assert_eq!(counts.len(), 9);
+        assert_eq!(*counts.get_mut_or_insert_default("cat"), 0);
+        assert_eq!(counts.len(), 10);
+    }
+
+    #[test]
+    fn test_insert_after_tomb_overwrites() {
+        let mut table = HashMap::with_exact_capacity(8, 1.0);
+        let hasher = table.hasher.clone();
+        let home = |key: &i32| HashMap::<i32, &str>::calculate_hash(key, &hasher) as usize % 8;
+        let collider = (1..).find(|i| home(i) == home(&0)).unwrap();
+        table.insert(0, "zero");
+        table.insert(collider, "collider");
+        table.remove(&0);
+        assert_eq!(table.insert(collider, "new_collider"), Some("collider"));
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.remove(&collider), Some("new_collider"));
+        assert_eq!(table.get(&collider), None);
+    }
+
+    #[test]
+    fn test_insert_tracked() {
+        let mut table = HashMap::with_load_factor(0.5);
+        for i in 0..1000_usize {
+            let (old, rehashed) = table.insert_tracked(i, i);
+            assert_eq!(old, None);
+            assert_eq!(rehashed, i >= 32 && i.is_power_of_two());
+        }
+        assert_eq!(table.insert_tracked(0, 1), (Some(0), false));
+    }
+
+    #[test]
+    fn test_clear_resets_tombs() {
+        let mut table = HashMap::new();
+        let hasher = table.hasher.clone();
+        let home = |key: &i32| HashMap::<i32, i32>::calculate_hash(key, &hasher) as usize % 64;
+        let keys: Vec<i32> = (0..).filter(|i| home(i) == home(&0)).take(10).collect();
+        for &key in &keys {
+            table.insert(key, key);
+        }
+        for key in &keys[..5] {
+            table.remove(key);
+        }
+        assert_eq!(table.tomb_count, 5);
+        table.clear();
+        assert_eq!(table.tomb_count, 0);
+        assert_eq!(table.tomb_factor(), 0.0);
+        assert_eq!(table.insert_tracked(1, 1), (None, false));
+    }
+
+    #[test]
+    fn test_shrink_to_fit() {
+        let mut table = HashMap::new();
+        for i in 0..1000 {
+            table.insert(i, i);
+        }
+        for i in 10..1000 {
+            table.remove(&i);
+        }
+        table.shrink_to_fit();
+        assert_eq!(table.buckets.len(), 26);
+        assert_eq!(table.tomb_count, 0);
+        assert_eq!(table.len(), 10);
+        for i in 0..10 {
+            assert_eq!(table.get(&i), Some(&i));
+        }
+        assert_eq!(table.get(&10), None);
+        table.clear();
+        table.shrink_to_fit();
+        assert!(table.buckets.is_empty());
+        table.insert(1, 1);
+        assert_eq!(table.get(&1), Some(&1));
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn test_rehash_capacity_overflow() {
+        scaled_capacity(usize::MAX / 2 + 1, 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn test_with_capacity_overflow() {
+        HashMap::<u8, u8>::with_capacity(usize::MAX / 4);
+    }
+
+    #[test]
+    fn test_load_factor() {
+        let table: HashMap<i32, i32> = HashMap::with_load_factor(0.7);
+        assert_eq!(table.load_factor(), 0.7);
+        assert_eq!(table.fill_factor(), 0.0);
+    }
+
+    #[test]
+    fn test_get_mut_compacts_tombs() {
+        let mut table = HashMap::with_exact_capacity(64, 1.0);
+        let hasher = table.hasher.clone();
+        let home = |key: &i32| HashMap::<i32, i32>::calculate_hash(key, &hasher) as usize % 64;
+        let keys: Vec<i32> = (0..).filter(|i| home(i) == home(&0)).take(5).collect();
+        let probe_length = |table: &HashMap<i32, i32>, key: &i32| {
+            let index = table.find_entry(key, HashMap::<i32, i32>::calculate_hash(key, &hasher));
+            (index.unwrap() + 64 - home(key)) % 64
+        };
+        for &key in &keys {
+            table.insert(key, key);
+        }
+        for key in &keys[..3] {
+            table.remove(key);
+        }
+        assert_eq!(probe_length(&table, &keys[4]), 4);
+        assert_eq!(table.get_mut(&keys[4]), Some(&mut keys[4].clone()));
+        assert_eq!(probe_length(&table, &keys[4]), 0);
+        assert_eq!(table.get_mut(&keys[3]), Some(&mut keys[3].clone()));
+        assert_eq!(probe_length(&table, &keys[3]), 1);
+        assert_eq!(table.get_mut(&keys[4]), Some(&mut keys[4].clone()));
+        assert_eq!(probe_length(&table, &keys[4]), 0);
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.tomb_count, 3);
+        for key in &keys[..3] {
+            assert_eq!(table.get(key), None);
+        }
+    }
+
+    #[test]
+    fn test_compact_in_place() {
+        let mut table = HashMap::new();
+        for i in 0..1000 {
+            table.insert(i, i);
+        }
+        for i in (0..1000).step_by(2) {
+            table.remove(&i);
+        }
+        let buffer = table.buckets.as_ptr();
+        let bucket_count = table.buckets.len();
+        table.compact_in_place();
+        assert_eq!(table.buckets.as_ptr(), buffer);
+        assert_eq!(table.buckets.len(), bucket_count);
+        assert_eq!(table.tomb_count, 0);
+        assert_eq!(table.len(), 500);
+        for i in 0..1000 {
+            assert_eq!(table.get(&i), (i % 2 == 1).then_some(&i));
+        }
+    }
+
+    #[test]
+    fn test_compact_in_place_wrapping_cluster() {
+        let mut table = HashMap::with_exact_capacity(16, 1.0);
+        let hasher = table.hasher.clone();
+        let home = |key: &i32| HashMap::<i32, i32>::calculate_hash(key, &hasher) as usize % 16;
+        let keys: Vec<i32> = (0..).filter(|i| home(i) == 14).take(6).collect();
+        for &key in &keys {
+            table.insert(key, key);
+        }
+        table.remove(&keys[0]);
+        table.remove(&keys[2]);
+        table.compact_in_place();
+        assert_eq!(table.tomb_count, 0);
+        for &key in &keys[1..] {
+            assert_eq!(table.get(&key), (key != keys[2]).then_some(&key));
+        }
+        assert_eq!(table.len(), 4);
+    }
+
+    #[test]
+    fn test_iter() {
+        let mut table = HashMap::new();
+        for i in 0..100 {
+            table.insert(i, i * 10);
+        }
+        for i in (0..100).filter(|i| i % 3 == 0) {
+            table.remove(&i);
+        }
+        assert!(table.tomb_count > 0);
+        assert_eq!(table.iter().len(), 66);
+        let mut entries: Vec<_> = table.iter().map(|(&k, &v)| (k, v)).collect();
+        entries.sort();
+        let expected: Vec<_> = (0..100)
+            .filter(|i| i % 3 != 0)
+            .map(|i| (i, i * 10))
+            .collect();
+        assert_eq!(entries, expected);
+        let mut keys: Vec<_> = table.keys().copied().collect();
+        keys.sort();
+        assert_eq!(keys, expected.iter().map(|&(k, _)| k).collect::<Vec<_>>());
+        let mut values: Vec<_> = table.values().copied().collect();
+        values.sort();
+        assert_eq!(values, expected.iter().map(|&(_, v)| v).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_try_insert() {
+        let mut table = HashMap::new();
+        assert_eq!(table.try_insert(1, "one").unwrap(), &mut "one");
+        *table.try_insert(2, "two").unwrap() = "second";
+        let error = table.try_insert(1, "uno").unwrap_err();
+        assert_eq!(error.key, 1);
+        assert_eq!(error.value, "uno");
+        assert_eq!(error.current, &mut "one");
+        *error.current = "first";
+        assert_eq!(table.get(&1), Some(&"first"));
+        assert_eq!(table.get(&2), Some(&"second"));
+        assert_eq!(table.len(), 2);
+
+        let mut table = HashMap::new();
+        for i in 0..1000 {
+            assert_eq!(table.try_insert(i, i.to_string()).unwrap(), &i.to_string());
+        }
+        assert_eq!(table.len(), 1000);
+        for i in 0..1000 {
+            assert_eq!(table.get(&i), Some(&i.to_string()));
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    struct CaseInsensitive(&'static str);
+
+    impl PartialEq for CaseInsensitive {
+        fn eq(&self, other: &Self) -> bool {
+            self.0.eq_ignore_ascii_case(other.0)
+        }
+    }
+
+    impl Eq for CaseInsensitive {}
+
+    impl Hash for CaseInsensitive {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.0.to_ascii_lowercase().hash(state);
+        }
+    }
+
+    #[test]
+    fn test_overwrite_keeps_new_key() {
+        let mut table = HashMap::new();
+        assert_eq!(table.insert(CaseInsensitive("Foo"), 1), None);
+        assert_eq!(table.insert(CaseInsensitive("foo"), 2), Some(1));
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.keys().next().unwrap().0, "foo");
+    }
+
+    #[test]
+    fn test_remove_at_end_of_cluster() {
+        let mut table = HashMap::with_exact_capacity(16, 1.0);
+        let hasher = table.hasher.clone();
+        let home = |key: &i32| HashMap::<i32, i32>::calculate_hash(key, &hasher) as usize % 16;
+        let keys: Vec<i32> = (0..).filter(|i| home(i) == home(&0)).take(3).collect();
+        for &key in &keys {
+            table.insert(key, key);
+        }
+        assert_eq!(table.remove(&keys[1]), Some(keys[1]));
+        assert_eq!(table.tomb_count, 1);
+        assert_eq!(table.remove(&keys[2]), Some(keys[2]));
+        assert_eq!(table.tomb_count, 0);
+        assert!(table
+            .buckets
+            .iter()
+            .all(|bucket| !matches!(bucket, Bucket::Tomb)));
+        assert_eq!(table.get(&keys[0]), Some(&keys[0]));
+        assert_eq!(table.get(&keys[1]), None);
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.remove(&keys[0]), Some(keys[0]));
+        assert_eq!(table.tomb_count, 0);
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn test_sorted_entries_mut() {
+        let mut table = HashMap::new();
+        for i in (0..100).rev() {
+            table.insert(i, 0);
+        }
+        table.remove(&50);
+        let entries = table.sorted_entries_mut();
+        assert_eq!(entries.len(), 99);
+        for (position, (_, value)) in entries.into_iter().enumerate() {
+            *value = position;
+        }
+        assert_eq!(table.get(&50), None);
+        for i in 0..50 {
+            assert_eq!(table.get(&i), Some(&i));
+        }
+        for i in 51..100 {
+            assert_eq!(table.get(&i), Some(&(i - 1)));
+        }
+    }
+
+    #[test]
+    fn test_remove_and_hint() {
+        let mut table = HashMap::with_exact_capacity(64, 1.0);
+        let hasher = table.hasher.clone();
+        table.set_tomb_threshold(0.1);
+        let home = |key: &i32| HashMap::<i32, i32>::calculate_hash(key, &hasher) as usize % 64;
+        let keys: Vec<i32> = (0..).filter(|i| home(i) == home(&0)).take(20).collect();
+        for &key in &keys {
+            table.insert(key, key);
+        }
+        for (removed, key) in keys[..10].iter().enumerate() {
+            // Every key but the last of the cluster leaves a tomb, 7 of 64 buckets exceed 0.1
+            assert_eq!(table.remove_and_hint(key), (Some(*key), removed >= 6));
+        }
+        table.compact_in_place();
+        assert_eq!(table.remove_and_hint(&keys[10]), (Some(keys[10]), false));
+        assert_eq!(table.remove_and_hint(&keys[10]), (None, false));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_rehash() {
+        let mut sequential = HashMap::new();
+        let mut parallel = HashMap::new();
+        for i in 0..100_000 {
+            sequential.insert(i, i * 2);
+            parallel.insert(i, i * 2);
+        }
+        for i in (0..100_000).step_by(3) {
+            sequential.remove(&i);
+            parallel.remove(&i);
+        }
+        sequential.rehash();
+        parallel.par_rehash();
+        assert_eq!(parallel.buckets.len(), sequential.buckets.len());
+        assert_eq!(parallel.len(), sequential.len());
+        assert_eq!(parallel.tomb_count, 0);
+        let mut expected: Vec<_> = sequential.iter().collect();
+        let mut actual: Vec<_> = parallel.iter().collect();
+        expected.sort();
+        actual.sort();
+        assert_eq!(actual, expected);
+        for i in 0..100_000 {
+            assert_eq!(parallel.get(&i), sequential.get(&i));
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_rehash_wrapping_cluster() {
+        let mut table = HashMap::with_exact_capacity(64, 1.0);
+        let hasher = table.hasher.clone();
+        let home = |key: &i32| HashMap::<i32, i32>::calculate_hash(key, &hasher) as usize % 128;
+        let keys: Vec<i32> = (0..).filter(|i| home(i) >= 120).take(40).collect();
+        for &key in &keys {
+            table.insert(key, key);
+        }
+        table.par_rehash();
+        assert_eq!(table.buckets.len(), 128);
+        for &key in &keys {
+            assert_eq!(table.get(&key), Some(&key));
+        }
+    }
+
+    #[test]
+    fn test_into_frozen() {
+        let mut table = HashMap::new();
+        for i in 0..1000 {
+            table.insert(i, i * 10);
+        }
+        for i in (0..1000).step_by(2) {
+            table.remove(&i);
+        }
+        assert!(table.tomb_count > 0);
+        let frozen = table.into_frozen();
+        assert_eq!(frozen.len(), 500);
+        assert_eq!(frozen.iter().len(), 500);
+        assert!(!frozen
+            .buckets
+            .iter()
+            .any(|bucket| matches!(bucket, Bucket::Tomb)));
+        assert!(frozen.buckets.len() < 600);
+        for i in 0..1000 {
+            if i % 2 == 0 {
+                assert!(!frozen.contains_key(&i));
+            } else {
+                assert_eq!(frozen.get(&i), Some(&(i * 10)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_into_frozen_empty() {
+        let frozen = HashMap::<i32, i32>::new().into_frozen();
+        assert!(frozen.is_empty());
+        assert_eq!(frozen.get(&1), None);
+        assert_eq!(frozen.iter().next(), None);
+    }
+
+    #[test]
+    fn test_reserve() {
+        let mut table = HashMap::new();
+        for i in 0..2000 {
+            table.insert(i, i);
+        }
+        for i in (0..2000).step_by(2) {
+            table.remove(&i);
+        }
+        assert!(table.tomb_count > 0);
+        table.reserve(10_000);
+        assert_eq!(table.tomb_count, 0);
+        let bucket_count = table.buckets.len();
+        for i in 2000..12_000 {
+            assert_eq!(table.insert_tracked(i, i), (None, false));
+        }
+        assert_eq!(table.buckets.len(), bucket_count);
+        assert_eq!(table.len(), 11_000);
+        for i in (1..2000).step_by(2).chain(2000..12_000) {
+            assert_eq!(table.get(&i), Some(&i));
+        }
+    }
+
+    #[test]
+    fn test_reserve_compacts_without_growing() {
+        let mut table = HashMap::with_exact_capacity(1024, 0.5);
+        for i in 0..300 {
+            table.insert(i, i);
+        }
+        for i in 0..200 {
+            table.remove(&i);
+        }
+        table.reserve(100);
+        assert_eq!(table.buckets.len(), 1024);
+        assert_eq!(table.tomb_count, 0);
+        for i in 200..300 {
+            assert_eq!(table.get(&i), Some(&i));
+        }
+    }
+
+    #[test]
+    fn test_into_iter_physical_order() {
+        let mut table = HashMap::new();
+        for i in 0..500 {
+            table.insert(i, i * 2);
+        }
+        for i in (0..500).step_by(3) {
+            table.remove(&i);
+        }
+        let slot_of: std::collections::HashMap<_, _> = table
+            .buckets
+            .iter()
+            .enumerate()
+            .filter_map(|(index, bucket)| match bucket {
+                Bucket::Entry(entry) => Some((entry.key, index)),
+                _ => None,
+            })
+            .collect();
+        let into_iter = table.into_iter();
+        assert_eq!(into_iter.len(), 333);
+        let indices: Vec<_> = into_iter
+            .map(|(key, value)| {
+                assert_eq!(value, key * 2);
+                slot_of[&key]
+            })
+            .collect();
+        assert_eq!(indices.len(), 333);
+        assert!(indices.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_drain_physical_order() {
+        let mut table = HashMap::new();
+        for i in 0..500 {
+            table.insert(i, i);
+        }
+        for i in (0..500).step_by(3) {
+            table.remove(&i);
+        }
+        let expected: Vec<_> = table.iter().map(|(&key, &value)| (key, value)).collect();
+        let bucket_count = table.buckets.len();
+        let drained: Vec<_> = table.drain().collect();
+        assert_eq!(drained, expected);
+        assert!(table.is_empty());
+        assert_eq!(table.tomb_count, 0);
+        assert_eq!(table.buckets.len(), bucket_count);
+        assert!(table
+            .buckets
+            .iter()
+            .all(|bucket| matches!(bucket, Bucket::None)));
+
+        for i in 0..100 {
+            table.insert(i, i);
+        }
+        let mut drain = table.drain();
+        assert_eq!(drain.len(), 100);
+        drain.next();
+        drop(drain);
+        assert!(table.is_empty());
+        assert!(table
+            .buckets
+            .iter()
+            .all(|bucket| matches!(bucket, Bucket::None)));
+        table.insert(1, 1);
+        assert_eq!(table.get(&1), Some(&1));
+    }
+
+    #[test]
+    fn test_replace_value() {
+        let mut table = HashMap::new();
+        assert_eq!(table.replace_value(&1, 10), None);
+        assert_eq!(table.get(&1), None);
+        for i in 0..100 {
+            table.insert(i, i);
+        }
+        for i in 0..100 {
+            assert_eq!(table.replace_value(&i, i + 1000), Some(i));
+        }
+        assert_eq!(table.replace_value(&100, 0), None);
+        assert_eq!(table.get(&100), None);
+        assert_eq!(table.len(), 100);
+        for i in 0..100 {
+            assert_eq!(table.get(&i), Some(&(i + 1000)));
+        }
+    }
+
+    #[test]
+    fn test_extend_grows_once() {
+        let mut table = HashMap::new();
+        table.insert(-1, -1);
+        table.extend((0..10_000).map(|i| (i, i * 2)));
+        assert_eq!(table.buckets.len(), (10_001_f64 / 0.4) as usize + 1);
+        assert_eq!(table.len(), 10_001);
+        assert_eq!(table.get(&-1), Some(&-1));
+        for i in 0..10_000 {
+            assert_eq!(table.get(&i), Some(&(i * 2)));
+        }
+    }
+
+    #[test]
+    fn test_verify_invariants() {
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+        let mut table = HashMap::new();
+        table.verify_invariants();
+        for round in 0..20_000 {
+            let key = rng.gen_range(0..500);
+            if rng.gen_bool(0.6) {
+                table.insert(key, round);
+            } else {
+                table.remove(&key);
+            }
+            if round % 1000 == 0 {
+                table.verify_invariants();
+            }
+        }
+        table.verify_invariants();
+        table.compact_in_place();
+        table.verify_invariants();
+    }
+
+    #[test]
+    fn test_from_pairs_checked() {
+        let table = HashMap::from_pairs_checked((0..1000).map(|i| (i, i * 2))).unwrap();
+        assert_eq!(table.len(), 1000);
+        for i in 0..1000 {
+            assert_eq!(table.get(&i), Some(&(i * 2)));
+        }
+        let pairs = (0..1000).map(|i| (i, i)).chain([(500, 0), (600, 0)]);
+        match HashMap::from_pairs_checked(pairs) {
+            Err(error) => assert_eq!(error.key, 500),
+            Ok(_) => panic!("duplicate key was not detected"),
+        }
+        assert!(HashMap::<i32, i32>::from_pairs_checked([])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_with_exact_entries() {
+        let mut table = HashMap::with_exact_entries(1000, 0.5);
+        assert_eq!(table.buckets.len(), 1999);
+        for i in 0..1000 {
+            assert_eq!(table.insert_tracked(i, i), (None, false));
+        }
+        assert_eq!(table.buckets.len(), 1999);
+        assert_eq!(table.insert_tracked(1000, 1000), (None, true));
+
+        let mut table = HashMap::with_exact_entries(10, 1.0);
+        assert_eq!(table.buckets.len(), 11);
+        for i in 0..10 {
+            assert_eq!(table.insert_tracked(i, i), (None, false));
+        }
+        for i in 0..10 {
+            assert_eq!(table.get(&i), Some(&i));
+        }
+        assert!(table.buckets.iter().any(|b| matches!(b, Bucket::None)));
+        assert!(HashMap::<i32, i32>::with_exact_entries(0, 0.5)
+            .buckets
+            .is_empty());
+    }
+
+    #[test]
+    fn test_get_compares_only_equal_hashes() {
+        use std::cell::Cell;
+
+        thread_local! {
+            static COMPARISONS: Cell<usize> = const { Cell::new(0) };
+        }
+        // Hashes only the explicit hash field and counts every key comparison
+        struct Counted {
+            id: u32,
+            hash: u64,
+        }
+        impl Hash for Counted {
+            fn hash<H: Hasher>(&self, state: &mut H) {
+                state.write_u64(self.hash);
+            }
+        }
+        impl PartialEq for Counted {
+            fn eq(&self, other: &Self) -> bool {
+                COMPARISONS.with(|comparisons| comparisons.set(comparisons.get() + 1));
+                self.id == other.id
+            }
+        }
+        impl Eq for Counted {}
+        let comparisons = || COMPARISONS.with(|comparisons| comparisons.replace(0));
+
+        let mut table = HashMap::with_exact_capacity(64, 0.9);
+        let hasher = table.hasher.clone();
+        let home = |hash: u64| {
+            HashMap::<Counted, u32>::calculate_hash(&Counted { id: 0, hash }, &hasher) as usize % 64
+        };
+        let mut hashes = (0..).filter(|&hash| home(hash) == home(0));
+        let shared = hashes.next().unwrap();
+        let distinct: Vec<u64> = hashes.take(10).collect();
+        for (id, &hash) in distinct.iter().enumerate() {
+            table.insert(
+                Counted {
+                    id: id as u32,
+                    hash,
+                },
+                id as u32,
+            );
+        }
+        for id in 100..103 {
+            table.insert(Counted { id, hash: shared }, id);
+        }
+
+        comparisons();
+        assert_eq!(
+            table.get(&Counted {
+                id: 200,
+                hash: shared
+            }),
+            None
+        );
+        assert_eq!(comparisons(), 3);
+        assert_eq!(
+            table.get(&Counted {
+                id: 101,
+                hash: shared
+            }),
+            Some(&101)
+        );
+        assert_eq!(comparisons(), 2);
+        let hash = distinct[5];
+        assert_eq!(table.get(&Counted { id: 5, hash }), Some(&5));
+        assert_eq!(comparisons(), 1);
+    }
+
+    #[test]
+    fn test_rehash_hook() {
+        use std::sync::{Arc, Mutex};
+
+        let rehashes = Arc::new(Mutex::new(Vec::new()));
+        let mut table = HashMap::with_load_factor(0.5);
+        let recorded = rehashes.clone();
+        table.set_rehash_hook(move |old, new| recorded.lock().unwrap().push((old, new)));
+        for i in 0..1000 {
+            table.insert(i, i);
+        }
+        assert_eq!(
+            *rehashes.lock().unwrap(),
+            vec![(64, 128), (128, 256), (256, 512), (512, 1024), (1024, 2048)]
+        );
+        for i in 0..1000 {
+            assert_eq!(table.get(&i), Some(&i));
+        }
+    }
+
+    #[test]
+    fn test_rehash_hook_reports_every_resize() {
+        use std::sync::Arc;
+
+        let rehashes = Arc::new(Mutex::new(Vec::new()));
+        let mut table = HashMap::new();
+        let recorded = rehashes.clone();
+        table.set_rehash_hook(move |old, new| recorded.lock().unwrap().push((old, new)));
+        for i in 0..10 {
+            table.insert(i, i);
+        }
+        table.remove(&0);
+        table.compact_in_place();
+        table.reserve(100);
+        table.shrink_to_fit();
+        for i in 1..10 {
+            table.remove(&i);
+        }
+        table.shrink_to_fit();
+        assert_eq!(
+            *rehashes.lock().unwrap(),
+            vec![(64, 64), (64, 273), (273, 23), (23, 0)]
+        );
+    }
+
+    #[test]
+    fn test_capacity_before_first_insert() {
+        let mut table = HashMap::new();
+        assert_eq!(table.capacity(), 0);
+        table.insert(1, 1);
+        assert!(table.capacity() > 0);
+        assert!(table.capacity() >= table.len());
+    }
+
+    #[test]
+    fn test_get_probe_length() {
+        let mut table = HashMap::with_exact_capacity(64, 1.0);
+        let hasher = table.hasher.clone();
+        let home = |key: &i32| HashMap::<i32, i32>::calculate_hash(key, &hasher) as usize % 64;
+        let keys: Vec<i32> = (0..).filter(|i| home(i) == home(&0)).take(3).collect();
+        for &key in &keys {
+            table.insert(key, key);
+        }
+        assert_eq!(table.get_probe_length(&keys[0]), Some(1));
+        assert_eq!(table.get_probe_length(&keys[1]), Some(2));
+        assert_eq!(table.get_probe_length(&keys[2]), Some(3));
+        table.remove(&keys[1]);
+        assert_eq!(table.get_probe_length(&keys[1]), None);
+        assert_eq!(table.get_probe_length(&keys[2]), Some(3));
+        let missing = (0..).find(|i| !keys.contains(i)).unwrap();
+        assert_eq!(table.get_probe_length(&missing), None);
+    }
+
+    #[test]
+    fn test_seeded_distribution() {
+        fn layout(table: &HashMap<i32, i32>, keys: &[i32]) -> Vec<usize> {
+            keys.iter()
+                .map(|key| {
+                    HashMap::<i32, i32>::calculate_hash(key, &table.hasher) as usize
+                        % table.buckets.len()
+                })
+                .collect()
+        }
+        // Keys that all share one bucket of a 64 bucket table hashed with seed 0
+        let home = |key: &i32| {
+            HashMap::<i32, i32>::calculate_hash(key, &SeededState { seed: 0 }) as usize % 64
+        };
+        let keys: Vec<i32> = (0..).filter(|key| home(key) == home(&0)).take(20).collect();
+        let mut random = [HashMap::new(), HashMap::new()];
+        let mut seeded = [HashMap::with_seed(0), HashMap::with_seed(0)];
+        for &key in &keys {
+            for table in random.iter_mut() {
+                table.insert(key, key);
+            }
+            for table in seeded.iter_mut() {
+                table.insert(key, key);
+            }
+        }
+        assert_ne!(layout(&random[0], &keys), layout(&random[1], &keys));
+        assert!(layout(&random[0], &keys)
+            .iter()
+            .any(|&bucket| bucket != home(&0)));
+        assert_eq!(layout(&seeded[0], &keys), vec![home(&0); keys.len()]);
+        assert_eq!(layout(&seeded[0], &keys), layout(&seeded[1], &keys));
+        for key in &keys {
+            assert_eq!(random[0].get(key), Some(key));
+            assert_eq!(seeded[0].get(key), Some(key));
+        }
+    }
+
+    #[test]
+    fn test_zero_sized_value_layout() {
+        use std::mem::size_of;
+
+        // The key and its cached hash, nothing for the value
+        assert_eq!(size_of::<Entry<u64, ()>>(), size_of::<(u64, u64)>());
+        assert_eq!(
+            size_of::<Bucket<String, ()>>(),
+            size_of::<Entry<String, ()>>()
+        );
+    }
+
+    #[test]
+    fn test_insert_or_get() {
+        let mut table = HashMap::new();
+        assert_eq!(*table.insert_or_get("key", 1), 1);
+        assert_eq!(*table.insert_or_get("key", 2), 1);
+        assert_eq!(*table.insert_or_get("other", 3), 3);
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get(&"key"), Some(&1));
+    }
+
+    #[test]
+    fn test_update_matching() {
+        let mut table = HashMap::new();
+        for i in 0..100 {
+            table.insert(i, i);
+        }
+        table.remove(&0);
+        assert_eq!(
+            table.update_matching(|key| key % 2 == 0, |value| *value += 1000),
+            49
+        );
+        for i in 1..100 {
+            let expected = if i % 2 == 0 { i + 1000 } else { i };
+            assert_eq!(table.get(&i), Some(&expected));
+        }
+        assert_eq!(
+            table.update_matching(|key| *key > 1000, |_| unreachable!()),
+            0
+        );
+    }
+
+    #[test]
+    fn test_debug_layout() {
+        let mut table = HashMap::with_exact_capacity(8, 1.0);
+        let hasher = table.hasher.clone();
+        let home = |key: &i32| HashMap::<i32, i32>::calculate_hash(key, &hasher) as usize % 8;
+        let collider = (1..).find(|key| home(key) == home(&0)).unwrap();
+        table.insert(0, 0);
+        table.insert(collider, 1);
+        let layout = table.debug_layout();
+        assert_eq!(layout.lines().count(), 8);
+        assert!(layout.contains(&format!("{}: Entry(0)\n", home(&0))));
+        assert!(layout.contains(&format!("{}: Entry({collider})\n", (home(&0) + 1) % 8)));
+        assert!(!layout.contains("Tomb"));
+        table.remove(&0);
+        let layout = table.debug_layout();
+        assert!(layout.contains(&format!("{}: Tomb\n", home(&0))));
+        assert_eq!(layout.matches("None").count(), 6);
+    }
+
+    #[test]
+    fn test_insert_indexed() {
+        let mut table = HashMap::new();
+        let mut indices = Vec::new();
+        for i in 0..20 {
+            let (old, index) = table.insert_indexed(i, i * 10);
+            assert_eq!(old, None);
+            assert_eq!(table.get_at(index), Some((&i, &(i * 10))));
+            indices.push(index);
+        }
+        for (i, &index) in indices.iter().enumerate() {
+            let i = i as i32;
+            assert_eq!(table.get_at(index), Some((&i, &(i * 10))));
+        }
+        assert_eq!(table.insert_indexed(3, 0), (Some(30), indices[3]));
+        assert_eq!(table.get_at(indices[3]), Some((&3, &0)));
+        table.remove(&3);
+        assert_eq!(table.get_at(indices[3]), None);
+        assert_eq!(table.get_at(usize::MAX), None);
+    }
+
+    #[test]
+    fn test_with_hasher_constant_hash() {
+        // Every key hashes the same, so all of them end up in a single probe run
+        struct ConstantState;
+        struct ConstantHasher;
+        impl BuildHasher for ConstantState {
+            type Hasher = ConstantHasher;
+            fn build_hasher(&self) -> ConstantHasher {
+                ConstantHasher
+            }
+        }
+        impl Hasher for ConstantHasher {
+            fn finish(&self) -> u64 {
+                7
+            }
+            fn write(&mut self, _: &[u8]) {}
+        }
+
+        let mut table = HashMap::with_hasher(ConstantState);
+        for i in 0..500 {
+            table.insert(i, i * 2);
+        }
+        for i in (0..500).step_by(3) {
+            assert_eq!(table.remove(&i), Some(i * 2));
+        }
+        for i in 0..500 {
+            let expected = if i % 3 == 0 { None } else { Some(i * 2) };
+            assert_eq!(table.get(&i).copied(), expected);
+        }
+        assert_eq!(table.get_probe_length(&499), Some(500));
+        table.verify_invariants();
+
+        let mut table = HashMap::with_capacity_and_hasher(10, ConstantState);
+        table.insert(1, 1);
+        assert_eq!(table.buckets.len(), 80);
+    }
+
+    #[test]
+    fn test_with_hasher_is_reproducible() {
+        use std::hash::BuildHasherDefault;
+
+        let build = || {
+            let mut table = HashMap::with_hasher(BuildHasherDefault::<DefaultHasher>::default());
+            for i in 0..100 {
+                table.insert(i, i);
+            }
+            table.iter().map(|(&key, _)| key).collect::<Vec<_>>()
+        };
+        assert_eq!(build(), build());
+    }
+
+    #[test]
+    fn test_insert_all_counts_overwrites() {
+        let mut table = HashMap::new();
+        table.insert(-1, 0);
+        let source = (0..1000).chain((0..1000).step_by(4)).chain([-1]);
+        assert_eq!(table.insert_all(source.map(|i| (i, i))), 251);
+        assert_eq!(table.len(), 1001);
+        for i in -1..1000 {
+            assert_eq!(table.get(&i), Some(&i));
+        }
+        assert_eq!(table.insert_all(Vec::new()), 0);
+        table.verify_invariants();
+    }
+
+    #[test]
+    fn test_load_factor_is_capped_below_one() {
+        assert_eq!(
+            HashMap::<i32, i32>::with_load_factor(0.95).load_factor(),
+            0.95
+        );
+        for load_factor in [0.99, 1.0, 1.5, f64::INFINITY] {
+            let mut table = HashMap::with_load_factor(load_factor);
+            assert!(table.load_factor() < 1.0);
+            for i in 0..5000 {
+                table.insert(i, i);
+                assert!(table.buckets.iter().any(|b| matches!(b, Bucket::None)));
+            }
+            for i in 0..5000 {
+                assert_eq!(table.get(&i), Some(&i));
+                assert_eq!(table.get(&(i + 5000)), None);
+                assert_eq!(table.remove(&(i + 5000)), None);
+            }
+            table.reserve(10_000);
+            table.shrink_to_fit();
+            assert_eq!(table.len(), 5000);
+            table.verify_invariants();
+        }
+    }
+
+    #[test]
+    fn test_try_insert_bounded() {
+        let mut table = HashMap::with_max_capacity(100);
+        for i in 0..100 {
+            assert_eq!(table.try_insert_bounded(i, i.to_string()), Ok(None));
+        }
+        let bucket_count = table.buckets.len();
+        assert!(bucket_count <= 251);
+        assert_eq!(
+            table.try_insert_bounded(100, "new".to_string()),
+            Err((100, "new".to_string()))
+        );
+        assert_eq!(
+            table.try_insert_bounded(7, "seven".to_string()),
+            Ok(Some("7".to_string()))
+        );
+        assert_eq!(table.len(), 100);
+        assert_eq!(table.get(&100), None);
+        assert_eq!(table.get(&7).map(String::as_str), Some("seven"));
+
+        table.remove(&0);
+        assert_eq!(table.try_insert_bounded(100, "new".to_string()), Ok(None));
+        assert_eq!(table.buckets.len(), bucket_count);
+        assert!(table.try_insert_bounded(101, "new".to_string()).is_err());
+        table.verify_invariants();
+
+        let mut unbounded = HashMap::new();
+        for i in 0..1000 {
+            assert_eq!(unbounded.try_insert_bounded(i, i), Ok(None));
+        }
+        assert_eq!(unbounded.len(), 1000);
+    }
+
+    #[test]
+    fn test_drain_yields_inserted_pairs() {
+        let mut table = HashMap::new();
+        for round in 0..3 {
+            let mut inserted: Vec<_> = (0..1000).map(|i| (i, format!("{round}-{i}"))).collect();
+            for (key, value) in &inserted {
+                table.insert(*key, value.clone());
+            }
+            for key in (0..1000).step_by(7) {
+                table.remove(&key);
+            }
+            inserted.retain(|(key, _)| key % 7 != 0);
+            let bucket_count = table.buckets.len();
+            let mut drained: Vec<_> = table.drain().collect();
+            drained.sort();
+            assert_eq!(drained, inserted);
+            assert!(table.is_empty());
+            assert_eq!(table.tomb_count, 0);
+            assert_eq!(table.buckets.len(), bucket_count);
+            table.insert(-1, "after".to_string());
+            assert_eq!(table.get(&-1).map(String::as_str), Some("after"));
+            assert_eq!(table.remove(&-1), Some("after".to_string()));
+            table.verify_invariants();
+        }
+    }
+
+    #[test]
+    fn test_vacant_entry() {
+        fn parse_into(table: &mut HashMap<String, i32>, line: &str) -> Result<(), String> {
+            let (key, value) = line.split_once('=').ok_or("missing '='")?;
+            let slot = table
+                .vacant_entry(key.to_string())
+                .map_err(|key| format!("duplicate key {key}"))?;
+            let value = value.parse().map_err(|_| "not a number".to_string())?;
+            slot.insert(value);
+            Ok(())
+        }
+
+        let mut table = HashMap::new();
+        assert_eq!(parse_into(&mut table, "a=1"), Ok(()));
+        assert!(parse_into(&mut table, "b=x").is_err());
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.get(&"b".to_string()), None);
+        assert_eq!(table.iter().count(), 1);
+        assert_eq!(
+            parse_into(&mut table, "a=2"),
+            Err("duplicate key a".to_string())
+        );
+        assert_eq!(table.get(&"a".to_string()), Some(&1));
+        table.verify_invariants();
+
+        // Committing into a tomb on the probe path
+        let mut table = HashMap::with_exact_capacity(8, 1.0);
+        for i in 0..4 {
+            table.insert(i, i);
+        }
+        for i in 0..4 {
+            table.remove(&i);
+        }
+        let tombs = table.tomb_count;
+        for i in 0..4 {
+            let slot = table.vacant_entry(i).ok().unwrap();
+            assert_eq!(*slot.key(), i);
+            *slot.insert(i) += 10;
+        }
+        assert!(table.tomb_count <= tombs);
+        for i in 0..4 {
+            assert_eq!(table.get(&i), Some(&(i + 10)));
+            assert_eq!(table.vacant_entry(i).err(), Some(i));
+        }
+        assert_eq!(table.len(), 4);
+        table.verify_invariants();
+
+        // The first slot ever reserved allocates the buckets up front
+        let mut table: HashMap<i32, i32> = HashMap::new();
+        let slot = table.vacant_entry(1).ok().unwrap();
+        assert_eq!(slot.into_key(), 1);
+        assert!(table.is_empty());
+        assert!(table
+            .buckets
+            .iter()
+            .all(|bucket| matches!(bucket, Bucket::None)));
+    }
+
+    #[test]
+    fn test_lookup_in_table_full_of_tombs() {
+        let mut table = HashMap::with_exact_capacity(64, 0.5);
+        table.insert(1, 1);
+        // One live entry keeps the lookups from returning early, no empty bucket ends the probe
+        for bucket in table.buckets.iter_mut() {
+            if !matches!(bucket, Bucket::Entry(_)) {
+                *bucket = Bucket::Tomb;
+            }
+        }
+        table.tomb_count = 63;
+        assert_eq!(table.get(&2), None);
+        assert_eq!(table.get_mut(&2), None);
+        assert_eq!(table.get_probe_length(&2), None);
+        assert_eq!(table.remove(&2), None);
+        assert_eq!(table.get(&1), Some(&1));
+        assert_eq!(table.insert(2, 2), None);
+        assert_eq!(table.get(&1), Some(&1));
+        assert_eq!(table.get(&2), Some(&2));
+        table.verify_invariants();
+    }
 }
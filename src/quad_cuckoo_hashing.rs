@@ -1,6 +1,6 @@
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
-use std::mem;
+use std::{iter, mem, slice, vec};
 
 use rand::seq::IteratorRandom;
 use rand::Rng;
@@ -8,10 +8,18 @@ use rand::Rng;
 // Cuckoo hashing with an arbitrary amount of hash functions and linear probing
 pub struct HashMap<K: Hash + Eq, V> {
     buckets: Vec<Vec<Option<Entry<K, V>>>>,
+    // Top 8 bits of the hash that picks the first candidate bucket of the key in each slot, at
+    // bucket * bucket_size + slot. Slots whose fingerprint differs are skipped without comparing
+    // keys.
+    fingerprints: Vec<u8>,
+    // buckets.len() * bucket_size, kept in sync whenever the buckets are reallocated
+    total_slots: usize,
     bucket_size: usize,
-    hasher_vec: Vec<DefaultHasher>,
+    hasher_seeds: Vec<u64>,
     load_factor: f64,
     length: usize,
+    // Inserts that had to kick at least one entry, since the last clear
+    collision_events: u64,
 }
 
 struct Entry<K: Hash + Eq, V> {
@@ -19,16 +27,83 @@ struct Entry<K: Hash + Eq, V> {
     value: V,
 }
 
+#[derive(Debug)]
+pub struct OccupiedError<'a, K, V> {
+    pub key: K,
+    pub value: V,
+    pub current: &'a mut V,
+}
+
+pub struct IntoIter<K: Hash + Eq, V> {
+    buckets: vec::IntoIter<Vec<Option<Entry<K, V>>>>,
+    slots: vec::IntoIter<Option<Entry<K, V>>>,
+    remaining: usize,
+}
+
+pub struct Iter<'a, K: Hash + Eq, V> {
+    buckets: slice::Iter<'a, Vec<Option<Entry<K, V>>>>,
+    slots: slice::Iter<'a, Option<Entry<K, V>>>,
+    remaining: usize,
+}
+
+fn scaled_capacity(capacity: usize, factor: usize) -> usize {
+    capacity.checked_mul(factor).expect("capacity overflow")
+}
+
+// Rounds the slot count up to whole rows of `bucket_size * hasher_amount` slots,
+// so every hasher gets an equally sized chunk of full buckets
+fn rounded_slot_count(
+    entries: usize,
+    bucket_size: usize,
+    hasher_amount: usize,
+    load_factor: f64,
+) -> usize {
+    let slots = (entries as f64 / load_factor).ceil();
+    assert!(slots < usize::MAX as f64, "capacity overflow");
+    let row = scaled_capacity(bucket_size, hasher_amount);
+    scaled_capacity((slots as usize).div_ceil(row), row)
+}
+
+// High bit of every byte of an up to eight byte fingerprint group that may equal the fingerprint.
+// A byte right above a match can be flagged too, so callers still compare the keys.
+fn matching_bytes(group: &[u8], fingerprint: u8) -> u64 {
+    const LOW: u64 = 0x0101_0101_0101_0101;
+    const HIGH: u64 = 0x8080_8080_8080_8080;
+    let mut word = [0; 8];
+    word[..group.len()].copy_from_slice(group);
+    // Bytes equal to the fingerprint become zero
+    let word = u64::from_le_bytes(word) ^ (LOW * fingerprint as u64);
+    let mask = word.wrapping_sub(LOW) & !word & HIGH;
+    // The padding past the group would match a zero fingerprint
+    match group.len() {
+        8 => mask,
+        len => mask & ((1 << (len * 8)) - 1),
+    }
+}
+
 impl<K: Hash + Eq, V> HashMap<K, V> {
     pub fn new() -> Self {
         Self::with_exact_capacity(0, 4, 4, 0.8)
     }
     pub fn with_capacity(capacity: usize) -> Self {
-        Self::with_exact_capacity(capacity * 16, 4, 4, 0.8)
+        Self::with_rounded_capacity(capacity, 4, 4, 0.8)
     }
     pub fn with_load_factor(fill_factor: f64) -> Self {
         Self::with_exact_capacity(0, 4, 4, fill_factor)
     }
+    fn with_rounded_capacity(
+        entries: usize,
+        bucket_size: usize,
+        hasher_amount: usize,
+        load_factor: f64,
+    ) -> Self {
+        Self::with_exact_capacity(
+            rounded_slot_count(entries, bucket_size, hasher_amount, load_factor),
+            bucket_size,
+            hasher_amount,
+            load_factor,
+        )
+    }
     fn with_exact_capacity(
         capacity: usize,
         bucket_size: usize,
@@ -43,16 +118,13 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
             buckets: (0..(capacity / bucket_size))
                 .map(|_| (0..bucket_size).map(|_| None).collect())
                 .collect(),
+            fingerprints: vec![0; capacity],
+            total_slots: capacity,
             bucket_size,
-            hasher_vec: (0..hasher_amount)
-                .map(|_| {
-                    let mut hasher = DefaultHasher::new();
-                    hasher.write_u64(rng.gen::<u64>());
-                    hasher
-                })
-                .collect(),
+            hasher_seeds: (0..hasher_amount).map(|_| rng.gen::<u64>()).collect(),
             load_factor,
             length: 0,
+            collision_events: 0,
         }
     }
     pub fn len(&self) -> usize {
@@ -61,103 +133,244 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
     pub fn is_empty(&self) -> bool {
         self.length == 0
     }
+    pub fn capacity(&self) -> usize {
+        (self.total_slots as f64 * self.load_factor) as usize
+    }
+    pub fn load_factor(&self) -> f64 {
+        self.load_factor
+    }
     pub fn fill_factor(&self) -> f64 {
-        if self.buckets.is_empty() {
+        if self.total_slots == 0 {
             0.0
         } else {
-            self.length as f64 / (self.buckets.len() * self.buckets[0].len()) as f64
+            self.length as f64 / self.total_slots as f64
+        }
+    }
+    // Visits entries in (chunk, bucket, slot) order, stable while the table is unmodified
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            buckets: self.buckets.iter(),
+            slots: [].iter(),
+            remaining: self.length,
+        }
+    }
+    pub fn keys(&self) -> impl ExactSizeIterator<Item = &K> {
+        self.iter().map(|(key, _)| key)
+    }
+    pub fn values(&self) -> impl ExactSizeIterator<Item = &V> {
+        self.iter().map(|(_, value)| value)
+    }
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
+        self.buckets
+            .iter_mut()
+            .flatten()
+            .flatten()
+            .map(|entry| &mut entry.value)
+    }
+    // Index i counts the buckets with exactly i occupied slots
+    pub fn occupancy_report(&self) -> Vec<usize> {
+        let mut report = vec![0; self.bucket_size + 1];
+        for bucket in &self.buckets {
+            report[bucket.iter().filter(|slot| slot.is_some()).count()] += 1;
         }
+        report
+    }
+    pub fn collisions(&self) -> u64 {
+        self.collision_events
     }
     pub fn clear(&mut self) {
         self.length = 0;
+        self.collision_events = 0;
         for element in self.buckets.iter_mut().flat_map(|bucket| bucket.iter_mut()) {
             *element = None;
         }
     }
 
-    fn calculate_hash(key: &K, hasher: &DefaultHasher) -> u64 {
-        let mut hasher = hasher.clone();
+    fn calculate_hash(key: &K, seed: u64) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        hasher.write_u64(seed);
         key.hash(&mut hasher);
         hasher.finish()
     }
 
     pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.insert_entry(Entry { key, value }).0
+    }
+
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<&mut V, OccupiedError<'_, K, V>> {
+        if let Some((bucket, slot)) = self.find_slot(&key) {
+            return Err(OccupiedError {
+                current: &mut self.buckets[bucket][slot].as_mut().unwrap().value,
+                key,
+                value,
+            });
+        }
+        let (_, (bucket, slot)) = self.insert_entry(Entry { key, value });
+        Ok(&mut self.buckets[bucket][slot].as_mut().unwrap().value)
+    }
+
+    pub fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, f: F) -> &mut V {
+        let (bucket, slot) = match self.find_slot(&key) {
+            Some(location) => location,
+            None => self.insert_entry(Entry { key, value: f() }).1,
+        };
+        &mut self.buckets[bucket][slot].as_mut().unwrap().value
+    }
+
+    // Returns the replaced value and the (bucket, slot) the key ended up in
+    fn insert_entry(&mut self, mut entry: Entry<K, V>) -> (Option<V>, (usize, usize)) {
         if self.buckets.is_empty() {
             self.buckets = (0..64)
                 .map(|_| (0..self.bucket_size).map(|_| None).collect())
                 .collect();
+            self.total_slots = 64 * self.bucket_size;
+            self.fingerprints = vec![0; self.total_slots];
         }
         if self.fill_factor() >= self.load_factor {
             self.rehash(2);
         }
-        let mut entry = Entry { key, value };
 
-        debug_assert_eq!(self.buckets.len() % self.hasher_vec.len(), 0);
-        let chunk_size = self.buckets.len() / self.hasher_vec.len();
-        for _ in 0..self.length + 1 {
+        debug_assert_eq!(self.buckets.len() % self.hasher_seeds.len(), 0);
+        // Slot of the new entry, None while it is the one being kicked around
+        let mut placed = None;
+        for round in 0..self.length + 1 {
             // Replace duplicate entry if it exists
-            if let Some(old_entry) = self
-                .buckets
-                .chunks_exact_mut(chunk_size)
-                .zip(&self.hasher_vec)
-                .flat_map(|buckets_for_hash_function| {
-                    &mut buckets_for_hash_function.0[Self::calculate_hash(
-                        &entry.key,
-                        buckets_for_hash_function.1,
-                    ) as usize
-                        % buckets_for_hash_function.0.len()]
-                })
-                .flatten()
-                .find(|e| e.key == entry.key)
-            {
-                return Some(mem::replace(&mut old_entry.value, entry.value));
+            let (fingerprint, duplicate) = self.locate_slot(&entry.key);
+            if let Some((bucket, slot)) = duplicate {
+                let old_entry = self.place((bucket, slot), fingerprint, entry).unwrap();
+                return (Some(old_entry.value), (bucket, slot));
             }
             // Insert entry into an empty spot
-            else if let Some(empty_spot) = self
-                .buckets
-                .chunks_exact_mut(chunk_size)
-                .zip(&self.hasher_vec)
-                .flat_map(|buckets_for_hash_function| {
-                    &mut buckets_for_hash_function.0[Self::calculate_hash(
-                        &entry.key,
-                        buckets_for_hash_function.1,
-                    ) as usize
-                        % buckets_for_hash_function.0.len()]
-                })
-                .find(|e| e.is_none())
-            {
+            let empty_spot = self
+                .candidate_slots(&entry.key)
+                .find(|&(bucket, slot)| self.buckets[bucket][slot].is_none());
+            if let Some((bucket, slot)) = empty_spot {
                 self.length += 1;
-                let replaced = mem::replace(empty_spot, Some(entry));
+                let replaced = self.place((bucket, slot), fingerprint, entry);
                 debug_assert!(replaced.is_none());
-                return None;
+                return (None, placed.unwrap_or((bucket, slot)));
             }
             // Kick a random entry and replace it
-            else if let Some(kicked_entry) = self
-                .buckets
-                .chunks_exact_mut(chunk_size)
-                .zip(&self.hasher_vec)
-                .flat_map(|buckets_for_hash_function| {
-                    &mut buckets_for_hash_function.0[Self::calculate_hash(
-                        &entry.key,
-                        buckets_for_hash_function.1,
-                    ) as usize
-                        % buckets_for_hash_function.0.len()]
-                })
-                .flatten()
-                .choose(&mut rand::thread_rng())
-            {
-                entry = mem::replace(kicked_entry, entry);
+            let kicked_spot = self
+                .candidate_slots(&entry.key)
+                .filter(|&(bucket, slot)| self.buckets[bucket][slot].is_some())
+                .choose(&mut rand::thread_rng());
+            if let Some((bucket, slot)) = kicked_spot {
+                if round == 0 {
+                    self.collision_events += 1;
+                }
+                entry = self.place((bucket, slot), fingerprint, entry).unwrap();
+                placed = match placed {
+                    None => Some((bucket, slot)),
+                    Some(location) if location == (bucket, slot) => None,
+                    Some(location) => Some(location),
+                };
             }
         }
+        // Keep the new entry out of the rebuild so its slot is known afterwards
+        let new_entry = placed.map(|(bucket, slot)| self.buckets[bucket][slot].take().unwrap());
+        let collision_events = self.collision_events;
         self.rehash(1);
-        self.insert(entry.key, entry.value)
+        let result = match new_entry {
+            Some(new_entry) => {
+                self.insert_entry(entry);
+                self.insert_entry(new_entry)
+            }
+            None => self.insert_entry(entry),
+        };
+        self.collision_events = collision_events;
+        result
+    }
+
+    // Writes the entry and its fingerprint into the slot, returning the previous entry
+    fn place(
+        &mut self,
+        (bucket, slot): (usize, usize),
+        fingerprint: u8,
+        entry: Entry<K, V>,
+    ) -> Option<Entry<K, V>> {
+        self.fingerprints[bucket * self.bucket_size + slot] = fingerprint;
+        self.buckets[bucket][slot].replace(entry)
+    }
+
+    fn find_slot(&self, key: &K) -> Option<(usize, usize)> {
+        if self.is_empty() {
+            return None;
+        }
+        self.locate_slot(key).1
+    }
+
+    // The fingerprint of the key along with its slot, if it is stored
+    fn locate_slot(&self, key: &K) -> (u8, Option<(usize, usize)>) {
+        let (fingerprint, mut buckets) = self.locate(key);
+        let slot = buckets.find_map(|bucket| {
+            // Compare the whole fingerprint group of the bucket before looking at any key
+            let start = bucket * self.bucket_size;
+            let group = &self.fingerprints[start..start + self.bucket_size];
+            group.chunks(8).enumerate().find_map(|(word, bytes)| {
+                let mut mask = matching_bytes(bytes, fingerprint);
+                while mask != 0 {
+                    let slot = word * 8 + mask.trailing_zeros() as usize / 8;
+                    mask &= mask - 1;
+                    if matches!(&self.buckets[bucket][slot], Some(e) if e.key == *key) {
+                        return Some((bucket, slot));
+                    }
+                }
+                None
+            })
+        });
+        (fingerprint, slot)
+    }
+
+    // The first hash picks the first candidate bucket and also gives the fingerprint, the others
+    // are only computed once the buckets before them are passed
+    fn locate<'a>(&'a self, key: &'a K) -> (u8, impl Iterator<Item = usize> + 'a) {
+        let chunk_size = self.buckets.len() / self.hasher_seeds.len();
+        let first = Self::calculate_hash(key, self.hasher_seeds[0]);
+        let rest = self.hasher_seeds[1..]
+            .iter()
+            .map(move |&seed| Self::calculate_hash(key, seed));
+        let buckets = iter::once(first)
+            .chain(rest)
+            .enumerate()
+            .map(move |(chunk, hash)| chunk * chunk_size + hash as usize % chunk_size);
+        ((first >> 56) as u8, buckets)
+    }
+
+    fn candidate_buckets<'a>(&'a self, key: &'a K) -> impl Iterator<Item = usize> + 'a {
+        self.locate(key).1
+    }
+
+    fn candidate_slots<'a>(&'a self, key: &'a K) -> impl Iterator<Item = (usize, usize)> + 'a {
+        self.candidate_buckets(key)
+            .flat_map(move |bucket| (0..self.bucket_size).map(move |slot| (bucket, slot)))
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        let entries = self
+            .length
+            .checked_add(additional)
+            .expect("capacity overflow");
+        let slots = rounded_slot_count(
+            entries,
+            self.bucket_size,
+            self.hasher_seeds.len(),
+            self.load_factor,
+        );
+        if slots > self.total_slots {
+            self.resize(slots);
+        }
     }
 
     fn rehash(&mut self, resize_factor: usize) {
+        self.resize(scaled_capacity(self.total_slots, resize_factor));
+    }
+
+    fn resize(&mut self, slots: usize) {
         let mut new_table = HashMap::with_exact_capacity(
-            self.buckets.len() * self.bucket_size * resize_factor,
+            slots,
             self.bucket_size,
-            self.hasher_vec.len(),
+            self.hasher_seeds.len(),
             self.load_factor,
         );
         for entry in self.buckets.iter_mut().flatten() {
@@ -165,69 +378,75 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
                 new_table.insert(entry.key, entry.value);
             }
         }
+        new_table.collision_events = self.collision_events;
         mem::swap(self, &mut new_table);
     }
 
     pub fn get(&self, key: &K) -> Option<&V> {
-        if self.is_empty() {
-            return None;
-        }
-        self.buckets
-            .chunks_exact(self.buckets.len() / self.hasher_vec.len())
-            .zip(&self.hasher_vec)
-            .flat_map(|buckets_for_hash_function| {
-                &buckets_for_hash_function.0[Self::calculate_hash(key, buckets_for_hash_function.1)
-                    as usize
-                    % buckets_for_hash_function.0.len()]
-            })
-            .flatten()
-            .find(|e| e.key == *key)
-            .map(|e| &e.value)
+        let (bucket, slot) = self.find_slot(key)?;
+        self.buckets[bucket][slot].as_ref().map(|e| &e.value)
+    }
+
+    pub fn get_copied(&self, key: &K) -> Option<V>
+    where
+        V: Copy,
+    {
+        self.get(key).copied()
     }
 
     pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
-        if self.is_empty() {
-            return None;
-        }
-        let chunk_size = self.buckets.len() / self.hasher_vec.len();
-        self.buckets
-            .chunks_exact_mut(chunk_size)
-            .zip(&self.hasher_vec)
-            .flat_map(|buckets_for_hash_function| {
-                &mut buckets_for_hash_function.0[Self::calculate_hash(
-                    key,
-                    buckets_for_hash_function.1,
-                ) as usize
-                    % buckets_for_hash_function.0.len()]
-            })
-            .flatten()
-            .find(|e| e.key == *key)
-            .map(|e| &mut e.value)
+        let (bucket, slot) = self.find_slot(key)?;
+        self.buckets[bucket][slot].as_mut().map(|e| &mut e.value)
+    }
+
+    pub fn replace_value(&mut self, key: &K, value: V) -> Option<V> {
+        self.get_mut(key)
+            .map(|current| mem::replace(current, value))
     }
 
     pub fn remove(&mut self, key: &K) -> Option<V> {
-        if self.is_empty() {
-            return None;
-        }
-        let chunk_size = self.buckets.len() / self.hasher_vec.len();
-        self.buckets
-            .chunks_exact_mut(chunk_size)
-            .zip(&self.hasher_vec)
-            .flat_map(|buckets_for_hash_function| {
-                &mut buckets_for_hash_function.0[Self::calculate_hash(
-                    key,
-                    buckets_for_hash_function.1,
-                ) as usize
-                    % buckets_for_hash_function.0.len()]
-            })
-            .find_map(|e| match e {
-                Some(entry) if entry.key == *key => {
+        let (bucket, slot) = self.find_slot(key)?;
+        self.length -= 1;
+        self.buckets[bucket][slot].take().map(|e| e.value)
+    }
+
+    pub fn retain<F: FnMut(&K, &mut V) -> bool>(&mut self, mut f: F) {
+        for slot in self.buckets.iter_mut().flatten() {
+            if let Some(entry) = slot {
+                if !f(&entry.key, &mut entry.value) {
+                    *slot = None;
                     self.length -= 1;
-                    e.take()
                 }
-                _ => None,
-            })
-            .map(|e| e.value)
+            }
+        }
+    }
+
+    #[cfg(any(test, feature = "invariant-checks"))]
+    pub fn verify_invariants(&self) {
+        let mut count = 0;
+        for (bucket, slots) in self.buckets.iter().enumerate() {
+            assert_eq!(
+                slots.len(),
+                self.bucket_size,
+                "bucket has the wrong slot count"
+            );
+            for (slot, entry) in slots.iter().enumerate() {
+                if let Some(entry) = entry {
+                    assert_eq!(
+                        self.fingerprints[bucket * self.bucket_size + slot],
+                        self.locate(&entry.key).0,
+                        "slot has a stale fingerprint"
+                    );
+                    assert!(
+                        self.candidate_slots(&entry.key)
+                            .any(|candidate| candidate == (bucket, slot)),
+                        "entry is outside all of its candidate buckets"
+                    );
+                    count += 1;
+                }
+            }
+        }
+        assert_eq!(count, self.length, "length does not match the entry count");
     }
 }
 impl<K: Hash + Eq, V> Default for HashMap<K, V> {
@@ -236,6 +455,83 @@ impl<K: Hash + Eq, V> Default for HashMap<K, V> {
     }
 }
 
+impl<K: Hash + Eq, V> Extend<(K, V)> for HashMap<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> FromIterator<(K, V)> for HashMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut table = Self::new();
+        table.extend(iter);
+        table
+    }
+}
+
+impl<'a, K: Hash + Eq, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.slots.next() {
+                Some(Some(entry)) => {
+                    self.remaining -= 1;
+                    return Some((&entry.key, &entry.value));
+                }
+                Some(None) => {}
+                None => self.slots = self.buckets.next()?.iter(),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K: Hash + Eq, V> ExactSizeIterator for Iter<'_, K, V> {}
+
+impl<K: Hash + Eq, V> IntoIterator for HashMap<K, V> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            buckets: self.buckets.into_iter(),
+            slots: Vec::new().into_iter(),
+            remaining: self.length,
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.slots.next() {
+                Some(Some(entry)) => {
+                    self.remaining -= 1;
+                    return Some((entry.key, entry.value));
+                }
+                Some(None) => {}
+                None => self.slots = self.buckets.next()?.into_iter(),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K: Hash + Eq, V> ExactSizeIterator for IntoIter<K, V> {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -471,4 +767,464 @@ mod tests {
         assert_eq!(table.buckets.len(), 10_000 / 4);
         assert_eq!(table.fill_factor(), 1.0);
     }
+
+    #[test]
+    fn test_get_copied() {
+        let mut table = HashMap::new();
+        for i in 0..100_u64 {
+            table.insert(i, i * 3);
+        }
+        for i in 0..200_u64 {
+            assert_eq!(table.get_copied(&i), table.get(&i).copied());
+        }
+        assert_eq!(table.get_copied(&7), Some(21));
+        assert_eq!(table.get_copied(&100), None);
+    }
+
+    #[test]
+    fn test_iter() {
+        let mut table = HashMap::new();
+        for i in 0..1000 {
+            table.insert(i, i * 2);
+        }
+        table.remove(&500);
+        assert_eq!(table.iter().len(), 999);
+        let mut entries: Vec<_> = table.iter().map(|(&k, &v)| (k, v)).collect();
+        entries.sort();
+        let expected: Vec<_> = (0..1000)
+            .filter(|&i| i != 500)
+            .map(|i| (i, i * 2))
+            .collect();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn test_iter_order_is_stable() {
+        let mut table = HashMap::with_load_factor(0.99);
+        for i in 0..10_000 {
+            table.insert(i, i);
+        }
+        let first: Vec<_> = table.iter().collect();
+        let second: Vec<_> = table.iter().collect();
+        assert_eq!(first, second);
+        let keys: Vec<_> = table.keys().collect();
+        let values: Vec<_> = table.values().collect();
+        assert_eq!(keys, first.iter().map(|(k, _)| *k).collect::<Vec<_>>());
+        assert_eq!(values, first.iter().map(|(_, v)| *v).collect::<Vec<_>>());
+        assert_eq!(table.values().max(), Some(&9_999));
+    }
+
+    #[test]
+    fn test_values_mut() {
+        let mut table = HashMap::new();
+        for i in 0..100 {
+            table.insert(i, i);
+        }
+        for value in table.values_mut() {
+            *value *= 10;
+        }
+        for i in 0..100 {
+            assert_eq!(table.get(&i), Some(&(i * 10)));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn test_rehash_capacity_overflow() {
+        scaled_capacity(usize::MAX / 2 + 1, 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn test_with_capacity_overflow() {
+        HashMap::<u8, u8>::with_capacity(usize::MAX / 4);
+    }
+
+    #[test]
+    fn test_into_iter() {
+        let mut table = HashMap::new();
+        for i in 0..1000 {
+            table.insert(i.to_string(), i);
+        }
+        let iter = table.into_iter();
+        assert_eq!(iter.len(), 1000);
+        let mut entries: Vec<(String, i32)> = iter.collect();
+        entries.sort_by_key(|(_, value)| *value);
+        let expected: Vec<_> = (0..1000).map(|i| (i.to_string(), i)).collect();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn test_into_iter_partial_drop() {
+        let shared = std::rc::Rc::new(());
+        let mut table = HashMap::new();
+        for i in 0..100 {
+            table.insert(i, shared.clone());
+        }
+        assert_eq!(std::rc::Rc::strong_count(&shared), 101);
+        let mut iter = table.into_iter();
+        let taken: Vec<_> = iter.by_ref().take(10).collect();
+        assert_eq!(iter.len(), 90);
+        drop(iter);
+        assert_eq!(std::rc::Rc::strong_count(&shared), 11);
+        drop(taken);
+        assert_eq!(std::rc::Rc::strong_count(&shared), 1);
+    }
+
+    #[test]
+    fn test_load_factor() {
+        let table: HashMap<i32, i32> = HashMap::with_load_factor(0.7);
+        assert_eq!(table.load_factor(), 0.7);
+        assert_eq!(table.fill_factor(), 0.0);
+    }
+
+    #[test]
+    fn test_retain() {
+        let mut table = HashMap::new();
+        for i in 0..1000 {
+            table.insert(i, i);
+        }
+        table.retain(|_, value| *value % 2 == 0);
+        assert_eq!(table.len(), 500);
+        for i in 0..1000 {
+            if i % 2 == 0 {
+                assert_eq!(table.get(&i), Some(&i));
+            } else {
+                assert_eq!(table.get(&i), None);
+            }
+        }
+    }
+
+    #[test]
+    fn test_try_insert() {
+        let mut table = HashMap::new();
+        assert_eq!(table.try_insert(1, "one").unwrap(), &mut "one");
+        *table.try_insert(2, "two").unwrap() = "second";
+        let error = table.try_insert(1, "uno").unwrap_err();
+        assert_eq!(error.key, 1);
+        assert_eq!(error.value, "uno");
+        assert_eq!(error.current, &mut "one");
+        *error.current = "first";
+        assert_eq!(table.get(&1), Some(&"first"));
+        assert_eq!(table.get(&2), Some(&"second"));
+        assert_eq!(table.len(), 2);
+
+        let mut table = HashMap::new();
+        for i in 0..1000 {
+            assert_eq!(table.try_insert(i, i.to_string()).unwrap(), &i.to_string());
+        }
+        assert_eq!(table.len(), 1000);
+        for i in 0..1000 {
+            assert_eq!(table.get(&i), Some(&i.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_try_insert_with_kicks() {
+        let mut table = HashMap::with_load_factor(0.99);
+        for i in 0..10_000 {
+            let value = table.try_insert(i, i).unwrap();
+            assert_eq!(*value, i);
+            *value += 1;
+        }
+        assert_eq!(table.len(), 10_000);
+        for i in 0..10_000 {
+            assert_eq!(table.get(&i), Some(&(i + 1)));
+        }
+    }
+
+    #[test]
+    fn test_occupancy_report() {
+        let mut table = HashMap::with_load_factor(0.99);
+        assert_eq!(table.occupancy_report(), vec![0; 5]);
+        for i in 0.. {
+            table.insert(i, i);
+            if table.len() > 1000 && table.fill_factor() >= 0.95 {
+                break;
+            }
+        }
+        let report = table.occupancy_report();
+        assert_eq!(report.len(), 5);
+        assert_eq!(report.iter().sum::<usize>(), table.buckets.len());
+        let occupied: usize = report.iter().enumerate().map(|(i, count)| i * count).sum();
+        assert_eq!(occupied, table.len());
+        assert!(report[3] + report[4] > table.buckets.len() * 9 / 10);
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    struct CaseInsensitive(&'static str);
+
+    impl PartialEq for CaseInsensitive {
+        fn eq(&self, other: &Self) -> bool {
+            self.0.eq_ignore_ascii_case(other.0)
+        }
+    }
+
+    impl Eq for CaseInsensitive {}
+
+    impl Hash for CaseInsensitive {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.0.to_ascii_lowercase().hash(state);
+        }
+    }
+
+    #[test]
+    fn test_overwrite_keeps_new_key() {
+        let mut table = HashMap::new();
+        assert_eq!(table.insert(CaseInsensitive("Foo"), 1), None);
+        assert_eq!(table.insert(CaseInsensitive("foo"), 2), Some(1));
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.keys().next().unwrap().0, "foo");
+    }
+
+    #[test]
+    fn test_get_or_insert_with() {
+        let mut table = HashMap::with_load_factor(0.99);
+        let mut calls = 0;
+        for i in 0..10_000 {
+            let value = table.get_or_insert_with(i, || {
+                calls += 1;
+                i
+            });
+            assert_eq!(*value, i);
+            *value += 1;
+        }
+        assert_eq!(calls, 10_000);
+        for i in 0..10_000 {
+            assert_eq!(*table.get_or_insert_with(i, || unreachable!()), i + 1);
+            assert_eq!(table.get(&i), Some(&(i + 1)));
+        }
+        assert_eq!(table.len(), 10_000);
+    }
+
+    #[test]
+    fn test_capacity() {
+        assert_eq!(HashMap::<i32, i32>::new().capacity(), 0);
+        for n in [1, 3, 7, 10, 13, 17, 100, 1001] {
+            let mut table = HashMap::with_capacity(n);
+            assert!(table.capacity() >= n);
+            let slots = table.buckets.len() * table.bucket_size;
+            for i in 0..n {
+                table.insert(i, i);
+            }
+            assert_eq!(table.buckets.len() * table.bucket_size, slots);
+        }
+    }
+
+    #[test]
+    fn test_with_rounded_capacity_awkward_grid() {
+        for n in [1, 5, 11, 29, 64] {
+            let mut table = HashMap::with_rounded_capacity(n, 3, 5, 0.5);
+            assert!(table.capacity() >= n);
+            assert_eq!(table.buckets.len() % 5, 0);
+            assert!(table.buckets.iter().all(|bucket| bucket.len() == 3));
+            for i in 0..n {
+                table.insert(i, i);
+            }
+            for i in 0..n {
+                assert_eq!(table.get(&i), Some(&i));
+            }
+        }
+    }
+
+    #[test]
+    fn test_replace_value() {
+        let mut table = HashMap::new();
+        assert_eq!(table.replace_value(&1, 10), None);
+        assert_eq!(table.get(&1), None);
+        for i in 0..100 {
+            table.insert(i, i);
+        }
+        for i in 0..100 {
+            assert_eq!(table.replace_value(&i, i + 1000), Some(i));
+        }
+        assert_eq!(table.replace_value(&100, 0), None);
+        assert_eq!(table.get(&100), None);
+        assert_eq!(table.len(), 100);
+        for i in 0..100 {
+            assert_eq!(table.get(&i), Some(&(i + 1000)));
+        }
+    }
+
+    #[test]
+    fn test_collisions() {
+        let mut table = HashMap::with_exact_capacity(8, 2, 2, 0.8);
+        let mut inserted = 0;
+        while table.collisions() == 0 {
+            table.insert(inserted, inserted);
+            inserted += 1;
+            assert!(inserted < 1000);
+        }
+        let collisions = table.collisions();
+        table.insert(0, 0);
+        assert_eq!(table.collisions(), collisions);
+        for i in inserted..1000 {
+            table.insert(i, i);
+        }
+        assert!(table.collisions() > collisions);
+        assert!(table.collisions() < 1000);
+        for i in 0..1000 {
+            assert_eq!(table.get(&i), Some(&i));
+        }
+        table.clear();
+        assert_eq!(table.collisions(), 0);
+    }
+
+    #[test]
+    fn test_extend_grows_once() {
+        let mut table = HashMap::new();
+        table.insert(-1, -1);
+        table.extend((0..10_000).map(|i| (i, i * 2)));
+        assert_eq!(
+            table.buckets.len() * table.bucket_size,
+            rounded_slot_count(10_001, 4, 4, 0.8)
+        );
+        assert_eq!(table.len(), 10_001);
+        assert_eq!(table.get(&-1), Some(&-1));
+        for i in 0..10_000 {
+            assert_eq!(table.get(&i), Some(&(i * 2)));
+        }
+    }
+
+    #[test]
+    fn test_verify_invariants() {
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+        let mut table = HashMap::new();
+        table.verify_invariants();
+        for round in 0..20_000 {
+            let key = rng.gen_range(0..500);
+            if rng.gen_bool(0.6) {
+                table.insert(key, round);
+            } else {
+                table.remove(&key);
+            }
+            if round % 1000 == 0 {
+                table.verify_invariants();
+            }
+        }
+        table.verify_invariants();
+    }
+
+    #[test]
+    fn test_fingerprints_match_reference() {
+        let mut rng = rand::thread_rng();
+        let mut table = HashMap::with_load_factor(0.95);
+        let mut reference = std::collections::HashMap::new();
+        for round in 0..50_000 {
+            let key = rng.gen_range(0..5_000);
+            match rng.gen_range(0..3) {
+                0 | 1 => assert_eq!(table.insert(key, round), reference.insert(key, round)),
+                _ => assert_eq!(table.remove(&key), reference.remove(&key)),
+            }
+        }
+        table.verify_invariants();
+        assert_eq!(table.len(), reference.len());
+        for key in 0..5_000 {
+            assert_eq!(table.get(&key), reference.get(&key));
+            assert_eq!(table.get_mut(&key), reference.get_mut(&key));
+        }
+    }
+
+    #[test]
+    fn test_matching_bytes() {
+        let mut rng = rand::thread_rng();
+        for len in 1..=8 {
+            for _ in 0..1000 {
+                let group: Vec<u8> = (0..len).map(|_| rng.gen_range(0..4)).collect();
+                let fingerprint = rng.gen_range(0..4);
+                let mask = matching_bytes(&group, fingerprint);
+                assert!(len == 8 || mask >> (len * 8) == 0);
+                assert_eq!(mask & !0x8080_8080_8080_8080, 0);
+                for (byte, &candidate) in group.iter().enumerate() {
+                    if candidate == fingerprint {
+                        assert_ne!(mask & (0x80 << (byte * 8)), 0);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_total_slots() {
+        let mut table = HashMap::new();
+        assert_eq!(table.total_slots, 0);
+        assert_eq!(table.fill_factor(), 0.0);
+        let mut rehashes = 0;
+        let mut slots = 0;
+        for i in 0..10_000 {
+            table.insert(i, i);
+            if table.total_slots != slots {
+                slots = table.total_slots;
+                rehashes += 1;
+            }
+            assert_eq!(table.total_slots, table.buckets.len() * table.bucket_size);
+            assert_eq!(
+                table.fill_factor(),
+                table.len() as f64 / (table.buckets.len() * table.buckets[0].len()) as f64
+            );
+        }
+        assert!(rehashes > 3);
+        table.clear();
+        assert_eq!(table.total_slots, slots);
+        assert_eq!(table.fill_factor(), 0.0);
+    }
+
+    #[test]
+    fn test_capacity_before_first_insert() {
+        let mut table = HashMap::new();
+        assert_eq!(table.capacity(), 0);
+        table.insert(1, 1);
+        assert!(table.capacity() > 0);
+        assert!(table.capacity() >= table.len());
+    }
+
+    #[test]
+    fn test_from_iter() {
+        let table: HashMap<_, _> = (0..100_000).map(|i| (i, i + 1)).collect();
+        assert_eq!(table.len(), 100_000);
+        for i in 0..100_000 {
+            assert_eq!(table.get(&i), Some(&(i + 1)));
+        }
+        table.verify_invariants();
+    }
+
+    #[test]
+    fn test_extend_arbitrary_sizes() {
+        for size in [0, 1, 3, 7, 13, 97, 1001, 4099] {
+            let mut table: HashMap<_, _> = (0..size).map(|i| (i, i)).collect();
+            table.extend((size..size * 2 + 5).map(|i| (i, i)));
+            assert_eq!(table.len(), size * 2 + 5);
+            for i in 0..size * 2 + 5 {
+                assert_eq!(table.get(&i), Some(&i));
+            }
+            table.verify_invariants();
+        }
+    }
+
+    #[test]
+    fn test_zero_sized_value_layout() {
+        use std::mem::size_of;
+
+        assert_eq!(size_of::<Entry<u64, ()>>(), size_of::<u64>());
+        assert_eq!(size_of::<Option<Entry<String, ()>>>(), size_of::<String>());
+    }
+
+    #[test]
+    fn test_seeded_hash_matches_cloned_hasher() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            let seed = rng.gen::<u64>();
+            let mut seeded = DefaultHasher::new();
+            seeded.write_u64(seed);
+            for key in 0..100u64 {
+                let mut cloned = seeded.clone();
+                key.hash(&mut cloned);
+                assert_eq!(
+                    HashMap::<u64, u64>::calculate_hash(&key, seed),
+                    cloned.finish()
+                );
+            }
+        }
+    }
 }